@@ -1,35 +1,133 @@
 /* eslint-disable @typescript-eslint/no-explicit-any */
 "use client";
 
-import React, { useState } from "react";
+import React, { useEffect, useRef, useState } from "react";
+
+type Progress = {
+  jobId: string;
+  chunksTotal: number;
+  // Indexes rather than a counter: a chunk can show up both in the
+  // snapshot and as a live event
+  chunksDone: number[];
+  log: string[];
+  state: "queued" | "running" | "done" | "failed" | "cancelled";
+  mergedFile?: string;
+  error?: string;
+};
 
 export default function SpeechPage() {
   const [text, setText] = useState("");
+  const [progress, setProgress] = useState<Progress | null>(null);
+  const sourceRef = useRef<EventSource | null>(null);
+
+  // Close the stream when leaving the page
+  useEffect(() => () => sourceRef.current?.close(), []);
+
+  function log(line: string) {
+    setProgress((p) => (p ? { ...p, log: [...p.log, line] } : p));
+  }
+
+  function follow(jobId: string) {
+    sourceRef.current?.close();
+    const source = new EventSource(`/api/speech/jobs/${jobId}/events`);
+    sourceRef.current = source;
+
+    source.addEventListener("job-status", (e: MessageEvent) => {
+      const job = JSON.parse(e.data);
+      setProgress((p) =>
+        p
+          ? {
+              ...p,
+              chunksTotal: job.chunks_total,
+              chunksDone: job.chunks
+                .filter((c: any) => c.state === "done")
+                .map((c: any) => c.index),
+              state: job.state,
+              mergedFile: job.merged_file ?? undefined,
+              error: job.error ?? undefined,
+            }
+          : p,
+      );
+    });
+    source.addEventListener("chunk-started", (e: MessageEvent) => {
+      setProgress((p) => (p ? { ...p, state: "running" } : p));
+      log(`Chunk ${JSON.parse(e.data).index} started`);
+    });
+    source.addEventListener("chunk-finished", (e: MessageEvent) => {
+      const { index, cached } = JSON.parse(e.data);
+      setProgress((p) =>
+        p && !p.chunksDone.includes(index)
+          ? { ...p, chunksDone: [...p.chunksDone, index] }
+          : p,
+      );
+      log(`Chunk ${index} finished${cached ? " (cached)" : ""}`);
+    });
+    source.addEventListener("chunk-failed", (e: MessageEvent) => {
+      const { index, error } = JSON.parse(e.data);
+      log(`Chunk ${index} failed: ${error}`);
+    });
+    source.addEventListener("merge-started", () => log("Merging chunks..."));
+    source.addEventListener("job-complete", (e: MessageEvent) => {
+      const { state, merged_file, error } = JSON.parse(e.data);
+      setProgress((p) =>
+        p
+          ? {
+              ...p,
+              state,
+              mergedFile: merged_file ?? undefined,
+              error: error ?? undefined,
+            }
+          : p,
+      );
+      source.close();
+    });
+    // The server ends the stream once the job is over; don't reconnect
+    source.onerror = () => source.close();
+  }
 
-  function handleSubmit(e: any) {
+  async function handleSubmit(e: any) {
     e.preventDefault();
 
-    // Fire-and-forget: just call fetch with no awaits or .then handlers
-    fetch("/api/speech", {
+    const res = await fetch("/api/speech", {
       method: "POST",
-      headers: { "Content-Type": "application/json" },
+      headers: {
+        "Content-Type": "application/json",
+        // Lets the server recognize a retry of this same submission
+        "Idempotency-Key": crypto.randomUUID(),
+      },
       body: JSON.stringify({ input: text }),
     });
+    const body = await res.json();
 
-    // (Optional) You can clear the text or otherwise give user feedback immediately:
+    if (!res.ok) {
+      setProgress({
+        jobId: "",
+        chunksTotal: 0,
+        chunksDone: [],
+        log: [],
+        state: "failed",
+        error: body.error,
+      });
+      return;
+    }
+
+    setProgress({
+      jobId: body.job_id,
+      chunksTotal: 0,
+      chunksDone: [],
+      log: [],
+      state: "queued",
+    });
+    follow(body.job_id);
     setText("");
   }
 
   return (
     <div className="p-4 max-w-xl mx-auto">
-      <h1 className="text-xl font-bold mb-4">
-        Text-to-Speech Demo (Fire & Forget)
-      </h1>
+      <h1 className="text-xl font-bold mb-4">Text-to-Speech Demo</h1>
       <form onSubmit={handleSubmit} className="space-y-4">
         <div>
-          <label className="block mb-1 font-medium">
-            Text to Speak (no response):
-          </label>
+          <label className="block mb-1 font-medium">Text to Speak:</label>
           <textarea
             rows={5}
             value={text}
@@ -44,6 +142,25 @@ export default function SpeechPage() {
           Send Request
         </button>
       </form>
+
+      {progress && (
+        <div className="mt-6 space-y-2">
+          {progress.jobId && (
+            <p className="text-sm text-gray-500">Job {progress.jobId}</p>
+          )}
+          <p className="font-medium">
+            {progress.state}: {progress.chunksDone.length} / {progress.chunksTotal}{" "}
+            chunk(s)
+          </p>
+          {progress.mergedFile && <p>Merged file: {progress.mergedFile}</p>}
+          {progress.error && <p className="text-red-500">{progress.error}</p>}
+          <ul className="text-sm font-mono">
+            {progress.log.map((line, i) => (
+              <li key={i}>{line}</li>
+            ))}
+          </ul>
+        </div>
+      )}
     </div>
   );
 }
@@ -0,0 +1,112 @@
+//! A webhook endpoint for trying out speech job webhooks locally.
+//!
+//! Run it with `just hook`, register `http://localhost:9100/hook` as a
+//! webhook (with `SPEECH_WEBHOOK_ALLOW_PRIVATE = "true"`), and hand it the
+//! secret from the registration response with `POST /secret`. Every
+//! delivery's signature is then checked, and `GET /deliveries` lists what
+//! arrived, newest first.
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+
+const ADDR: &str = "127.0.0.1:9100";
+
+/// Deliveries older than this are refused, so a captured one can't be
+/// replayed later.
+const MAX_AGE_SECONDS: i64 = 300;
+
+#[derive(Clone, Default)]
+struct Receiver {
+    secret: Arc<Mutex<String>>,
+    deliveries: Arc<Mutex<Vec<Value>>>,
+}
+
+#[tokio::main]
+async fn main() {
+    let router = Router::new()
+        .route("/secret", post(set_secret))
+        .route("/hook", post(receive))
+        .route("/deliveries", get(deliveries))
+        .with_state(Receiver::default());
+
+    let listener = tokio::net::TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening for webhooks on http://{ADDR}/hook");
+    axum::serve(listener, router).await.unwrap();
+}
+
+async fn set_secret(State(receiver): State<Receiver>, body: String) -> StatusCode {
+    *receiver.secret.lock().unwrap() = body.trim().to_string();
+    StatusCode::NO_CONTENT
+}
+
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let timestamp = header("x-speech-timestamp");
+    let signature = header("x-speech-signature");
+
+    let secret = receiver.secret.lock().unwrap().clone();
+    let fresh = timestamp
+        .parse::<i64>()
+        .is_ok_and(|t| (chrono::Utc::now().timestamp() - t).abs() <= MAX_AGE_SECONDS);
+    let verified = fresh && verify(&secret, &timestamp, &body, &signature);
+
+    let payload: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    println!(
+        "Received {} delivery {}: signature {}",
+        header("x-speech-event"),
+        header("x-speech-delivery"),
+        if verified { "verified" } else { "INVALID" }
+    );
+    receiver.deliveries.lock().unwrap().insert(
+        0,
+        json!({
+            "event": header("x-speech-event"),
+            "delivery_id": header("x-speech-delivery"),
+            "verified": verified,
+            "payload": payload,
+        }),
+    );
+
+    if verified {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::UNAUTHORIZED
+    }
+}
+
+async fn deliveries(State(receiver): State<Receiver>) -> Json<Value> {
+    let deliveries = receiver.deliveries.lock().unwrap().clone();
+    Json(json!({ "deliveries": deliveries }))
+}
+
+/// Checks `X-Speech-Signature`: `sha256=` and the hex HMAC-SHA256 of
+/// `<timestamp>.<body>`, keyed with the webhook's secret.
+fn verify(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Some(expected) = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| hex::decode(hex).ok())
+    else {
+        return false;
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS speech_chunks;
+DROP TABLE IF EXISTS speech_jobs;
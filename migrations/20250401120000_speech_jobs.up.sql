@@ -0,0 +1,34 @@
+CREATE TABLE IF NOT EXISTS speech_jobs (
+    id UUID PRIMARY KEY,
+    user_id INT,
+    input_hash VARCHAR NOT NULL,
+    input_chars INT NOT NULL,
+    voice VARCHAR NOT NULL,
+    model VARCHAR NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'queued',
+    error TEXT,
+    chunks_total INT NOT NULL,
+    output_dir VARCHAR,
+    merged_path VARCHAR,
+    merged_bytes BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMPTZ,
+    foreign key (user_id) references users(id)
+);
+
+CREATE INDEX IF NOT EXISTS speech_jobs_user_id_idx ON speech_jobs (user_id);
+
+CREATE TABLE IF NOT EXISTS speech_chunks (
+    job_id UUID NOT NULL,
+    idx INT NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    error TEXT,
+    input_chars INT NOT NULL,
+    path VARCHAR,
+    bytes BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (job_id, idx),
+    foreign key (job_id) references speech_jobs(id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE speech_chunks DROP COLUMN IF EXISTS text;
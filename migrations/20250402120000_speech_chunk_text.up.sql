@@ -0,0 +1,2 @@
+-- Keep the text of every chunk so failed chunks can be re-synthesized later
+ALTER TABLE speech_chunks ADD COLUMN IF NOT EXISTS text TEXT;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS plans (
+    name VARCHAR PRIMARY KEY,
+    max_concurrency INT NOT NULL CHECK (max_concurrency > 0)
+);
+
+INSERT INTO plans (name, max_concurrency) VALUES
+    ('free', 2),
+    ('pro', 8)
+ON CONFLICT (name) DO NOTHING;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS plan VARCHAR NOT NULL DEFAULT 'free' REFERENCES plans(name);
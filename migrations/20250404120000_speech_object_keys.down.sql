@@ -0,0 +1,10 @@
+UPDATE speech_jobs SET output_prefix = 'speech-output/' || output_prefix
+WHERE output_prefix IS NOT NULL;
+UPDATE speech_jobs SET merged_key = 'speech-output/' || merged_key
+WHERE merged_key IS NOT NULL;
+UPDATE speech_chunks SET object_key = 'speech-output/' || object_key
+WHERE object_key IS NOT NULL;
+
+ALTER TABLE speech_chunks RENAME COLUMN object_key TO path;
+ALTER TABLE speech_jobs RENAME COLUMN merged_key TO merged_path;
+ALTER TABLE speech_jobs RENAME COLUMN output_prefix TO output_dir;
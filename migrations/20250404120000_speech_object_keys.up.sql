@@ -0,0 +1,13 @@
+-- Generated audio is addressed by storage key (relative to the storage
+-- root or bucket) instead of by a path relative to the working directory.
+ALTER TABLE speech_jobs RENAME COLUMN output_dir TO output_prefix;
+ALTER TABLE speech_jobs RENAME COLUMN merged_path TO merged_key;
+ALTER TABLE speech_chunks RENAME COLUMN path TO object_key;
+
+-- Paths under the old default root become keys in the local store
+UPDATE speech_jobs SET output_prefix = substr(output_prefix, length('speech-output/') + 1)
+WHERE output_prefix LIKE 'speech-output/%';
+UPDATE speech_jobs SET merged_key = substr(merged_key, length('speech-output/') + 1)
+WHERE merged_key LIKE 'speech-output/%';
+UPDATE speech_chunks SET object_key = substr(object_key, length('speech-output/') + 1)
+WHERE object_key LIKE 'speech-output/%';
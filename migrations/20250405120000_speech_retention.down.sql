@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS speech_jobs_unexpired_idx;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS expired_at;
+ALTER TABLE users DROP COLUMN IF EXISTS is_admin;
+ALTER TABLE users DROP COLUMN IF EXISTS retention_days;
+ALTER TABLE plans DROP COLUMN IF EXISTS retention_days;
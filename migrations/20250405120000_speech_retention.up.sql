@@ -0,0 +1,12 @@
+-- How long a plan's finished speech jobs keep their audio
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS retention_days INT NOT NULL DEFAULT 30 CHECK (retention_days > 0);
+UPDATE plans SET retention_days = 7 WHERE name = 'free';
+UPDATE plans SET retention_days = 90 WHERE name = 'pro';
+
+-- Overrides the plan's retention for a single user
+ALTER TABLE users ADD COLUMN IF NOT EXISTS retention_days INT CHECK (retention_days > 0);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT false;
+
+-- Set once the job's audio has been deleted for being past retention
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS expired_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS speech_jobs_unexpired_idx ON speech_jobs (finished_at) WHERE expired_at IS NULL;
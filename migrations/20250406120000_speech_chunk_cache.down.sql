@@ -0,0 +1,2 @@
+ALTER TABLE speech_chunks DROP COLUMN IF EXISTS cached;
+DROP TABLE IF EXISTS speech_chunk_cache;
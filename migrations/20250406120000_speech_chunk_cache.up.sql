@@ -0,0 +1,15 @@
+-- Synthesized chunk audio, shared across jobs and keyed by a hash of
+-- everything that affects the audio
+CREATE TABLE IF NOT EXISTS speech_chunk_cache (
+    hash VARCHAR PRIMARY KEY,
+    object_key VARCHAR NOT NULL,
+    bytes BIGINT NOT NULL,
+    hits BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS speech_chunk_cache_last_used_idx ON speech_chunk_cache (last_used_at);
+
+-- Whether a chunk's audio came from the cache rather than the provider
+ALTER TABLE speech_chunks ADD COLUMN IF NOT EXISTS cached BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS revision;
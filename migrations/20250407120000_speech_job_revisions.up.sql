@@ -0,0 +1,2 @@
+-- How many times a job's input has been submitted; bumped by each resubmit
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS revision INT NOT NULL DEFAULT 1;
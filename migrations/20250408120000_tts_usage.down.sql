@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS tts_usage;
+ALTER TABLE users DROP COLUMN IF EXISTS monthly_chars;
+ALTER TABLE plans DROP COLUMN IF EXISTS monthly_chars;
@@ -0,0 +1,21 @@
+-- Characters a plan may send to the TTS provider per calendar month (UTC)
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS monthly_chars BIGINT NOT NULL DEFAULT 100000 CHECK (monthly_chars >= 0);
+UPDATE plans SET monthly_chars = 100000 WHERE name = 'free';
+UPDATE plans SET monthly_chars = 2000000 WHERE name = 'pro';
+
+-- Overrides the plan's monthly characters for a single user
+ALTER TABLE users ADD COLUMN IF NOT EXISTS monthly_chars BIGINT CHECK (monthly_chars >= 0);
+
+-- One row per chunk sent to the TTS provider; cache hits are not recorded
+CREATE TABLE IF NOT EXISTS tts_usage (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id INT NOT NULL,
+    job_id UUID,
+    chunk_idx INT,
+    chars INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    foreign key (user_id) references users(id),
+    foreign key (job_id) references speech_jobs(id)
+);
+
+CREATE INDEX IF NOT EXISTS tts_usage_user_created_idx ON tts_usage (user_id, created_at);
@@ -0,0 +1,2 @@
+ALTER TABLE speech_chunks DROP COLUMN IF EXISTS error_code;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS error_code;
@@ -0,0 +1,3 @@
+-- Machine-readable counterpart of `error`, e.g. 'rate_limited'
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS error_code VARCHAR;
+ALTER TABLE speech_chunks ADD COLUMN IF NOT EXISTS error_code VARCHAR;
@@ -0,0 +1,2 @@
+-- Playback speed the job's chunks are synthesized at
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS speed REAL NOT NULL DEFAULT 1.0;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS speech_jobs_batch_id_idx;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS title;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS batch_idx;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS batch_id;
+DROP TABLE IF EXISTS speech_batches;
@@ -0,0 +1,16 @@
+-- A set of documents submitted together; each becomes its own speech job
+CREATE TABLE IF NOT EXISTS speech_batches (
+    id UUID PRIMARY KEY,
+    user_id INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    foreign key (user_id) references users(id)
+);
+
+CREATE INDEX IF NOT EXISTS speech_batches_user_id_idx ON speech_batches (user_id);
+
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS batch_id UUID REFERENCES speech_batches(id);
+-- Position of the job's document in its batch, from 1
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS batch_idx INT;
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS title VARCHAR;
+
+CREATE INDEX IF NOT EXISTS speech_jobs_batch_id_idx ON speech_jobs (batch_id);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS speech_jobs_idempotency_key_idx;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS request_hash;
+ALTER TABLE speech_jobs DROP COLUMN IF EXISTS idempotency_key;
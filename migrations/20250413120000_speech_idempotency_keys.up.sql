@@ -0,0 +1,8 @@
+-- The Idempotency-Key a job was submitted with, and a hash of the request,
+-- so that a retried submission finds the job instead of creating another
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS idempotency_key VARCHAR;
+ALTER TABLE speech_jobs ADD COLUMN IF NOT EXISTS request_hash VARCHAR;
+
+CREATE UNIQUE INDEX IF NOT EXISTS speech_jobs_idempotency_key_idx
+    ON speech_jobs (user_id, idempotency_key)
+    WHERE idempotency_key IS NOT NULL;
@@ -0,0 +1,2 @@
+-- How many times the TTS request for a chunk was retried in its latest run
+ALTER TABLE speech_chunks ADD COLUMN IF NOT EXISTS retries INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,190 @@
+use shuttle_runtime::SecretStore;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::services::tts_service::RetryPolicy;
+
+/// Tunables for the speech pipeline, read from `Secrets.toml`.
+#[derive(Clone)]
+pub struct SpeechConfig {
+    /// Upper bound on TTS requests in flight across all jobs.
+    pub max_concurrency: usize,
+    /// Where the adaptive limit starts before it has seen any responses.
+    pub initial_concurrency: usize,
+    /// Where generated audio is stored.
+    pub storage: StorageConfig,
+    /// Which provider synthesizes speech.
+    pub tts: TtsConfig,
+    /// How TTS requests that fail with a 429, a 5xx or a network error are
+    /// retried.
+    pub tts_retry: RetryPolicy,
+    /// How often the GC sweeps old speech audio. Chunk audio is also kept
+    /// for this long after its job finishes.
+    pub gc_interval: Duration,
+    /// Size budget of the synthesized chunk cache; 0 turns the cache off.
+    pub cache_max_bytes: u64,
+    /// Longest document, in characters, a speech request may submit.
+    pub max_input_chars: usize,
+    /// How many times a webhook delivery is attempted before giving up.
+    pub webhook_max_attempts: u32,
+    /// Wait before the first webhook retry; it doubles with each retry.
+    pub webhook_retry_base: Duration,
+    /// Whether webhooks may point at loopback and private network
+    /// addresses, for trying them out locally.
+    pub webhook_allow_private: bool,
+}
+
+/// Which backend holds generated audio, selected with `SPEECH_STORAGE`.
+#[derive(Clone)]
+pub enum StorageConfig {
+    /// Files under a directory on the local disk.
+    Local { root: PathBuf },
+    /// An S3 bucket. `endpoint` points at an S3-compatible server such as
+    /// MinIO instead of AWS.
+    S3 {
+        bucket: String,
+        region: String,
+        endpoint: Option<String>,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+    },
+}
+
+/// Which text-to-speech provider to use, selected with
+/// `SPEECH_TTS_PROVIDER`.
+#[derive(Clone)]
+pub enum TtsConfig {
+    /// OpenAI's speech API.
+    OpenAi { api_key: String },
+    /// Silent audio generated locally, for running without a network or
+    /// API credits.
+    Mock {
+        /// How long each request takes.
+        latency: Duration,
+        /// Fraction of requests answered with a 500.
+        failure_rate: f64,
+        /// Fraction of requests answered with a 429.
+        rate_limit_rate: f64,
+    },
+}
+
+impl SpeechConfig {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        let max_concurrency = secret_or(secrets, "SPEECH_MAX_CONCURRENCY", 8);
+        assert!(
+            max_concurrency > 0,
+            "SPEECH_MAX_CONCURRENCY must be at least 1"
+        );
+        let gc_interval_minutes: u64 = secret_or(secrets, "SPEECH_GC_INTERVAL_MINUTES", 60);
+        assert!(
+            gc_interval_minutes > 0,
+            "SPEECH_GC_INTERVAL_MINUTES must be at least 1"
+        );
+
+        let tts_max_attempts = secret_or(secrets, "SPEECH_TTS_MAX_ATTEMPTS", 4);
+        assert!(
+            tts_max_attempts > 0,
+            "SPEECH_TTS_MAX_ATTEMPTS must be at least 1"
+        );
+
+        let webhook_max_attempts = secret_or(secrets, "SPEECH_WEBHOOK_MAX_ATTEMPTS", 6);
+        assert!(
+            webhook_max_attempts > 0,
+            "SPEECH_WEBHOOK_MAX_ATTEMPTS must be at least 1"
+        );
+
+        Self {
+            max_concurrency,
+            initial_concurrency: secret_or(secrets, "SPEECH_INITIAL_CONCURRENCY", 4),
+            storage: StorageConfig::from_secrets(secrets),
+            tts: TtsConfig::from_secrets(secrets),
+            tts_retry: RetryPolicy {
+                max_attempts: tts_max_attempts,
+                base_delay: Duration::from_millis(secret_or(
+                    secrets,
+                    "SPEECH_TTS_RETRY_BASE_MS",
+                    500,
+                )),
+                max_delay: Duration::from_secs(secret_or(
+                    secrets,
+                    "SPEECH_TTS_RETRY_MAX_SECONDS",
+                    30,
+                )),
+            },
+            gc_interval: Duration::from_secs(gc_interval_minutes * 60),
+            cache_max_bytes: secret_or::<u64>(secrets, "SPEECH_CACHE_MAX_MB", 1024) * 1024 * 1024,
+            max_input_chars: secret_or(secrets, "SPEECH_MAX_INPUT_CHARS", 500_000),
+            webhook_max_attempts,
+            webhook_retry_base: Duration::from_secs(secret_or(
+                secrets,
+                "SPEECH_WEBHOOK_RETRY_SECONDS",
+                30,
+            )),
+            webhook_allow_private: secret_or(secrets, "SPEECH_WEBHOOK_ALLOW_PRIVATE", false),
+        }
+    }
+}
+
+impl StorageConfig {
+    fn from_secrets(secrets: &SecretStore) -> Self {
+        let backend = secrets.get("SPEECH_STORAGE");
+        match backend.as_deref().unwrap_or("local") {
+            "local" => StorageConfig::Local {
+                root: secret_or(secrets, "SPEECH_OUTPUT_ROOT", "speech-output".into()),
+            },
+            "s3" => StorageConfig::S3 {
+                bucket: secrets
+                    .get("SPEECH_S3_BUCKET")
+                    .expect("SPEECH_S3_BUCKET must be set when SPEECH_STORAGE is s3"),
+                region: secret_or(secrets, "SPEECH_S3_REGION", "us-east-1".to_string()),
+                endpoint: secrets.get("SPEECH_S3_ENDPOINT"),
+                access_key_id: secrets.get("SPEECH_S3_ACCESS_KEY_ID"),
+                secret_access_key: secrets.get("SPEECH_S3_SECRET_ACCESS_KEY"),
+            },
+            other => panic!("Secret SPEECH_STORAGE has an invalid value: {other}"),
+        }
+    }
+}
+
+impl TtsConfig {
+    fn from_secrets(secrets: &SecretStore) -> Self {
+        let provider = secrets.get("SPEECH_TTS_PROVIDER");
+        match provider.as_deref().unwrap_or("openai") {
+            "openai" => TtsConfig::OpenAi {
+                api_key: secrets
+                    .get("OPENAI_API_KEY")
+                    .expect("OPENAI_API_KEY must be set when SPEECH_TTS_PROVIDER is openai"),
+            },
+            "mock" => {
+                let failure_rate = secret_or(secrets, "SPEECH_MOCK_FAILURE_RATE", 0.0);
+                let rate_limit_rate = secret_or(secrets, "SPEECH_MOCK_RATE_LIMIT_RATE", 0.0);
+                assert!(
+                    (0.0..=1.0).contains(&failure_rate)
+                        && (0.0..=1.0).contains(&rate_limit_rate)
+                        && failure_rate + rate_limit_rate <= 1.0,
+                    "SPEECH_MOCK_FAILURE_RATE and SPEECH_MOCK_RATE_LIMIT_RATE must be between 0 and 1 and add up to at most 1"
+                );
+                TtsConfig::Mock {
+                    latency: Duration::from_millis(secret_or(
+                        secrets,
+                        "SPEECH_MOCK_LATENCY_MS",
+                        200,
+                    )),
+                    failure_rate,
+                    rate_limit_rate,
+                }
+            }
+            other => panic!("Secret SPEECH_TTS_PROVIDER has an invalid value: {other}"),
+        }
+    }
+}
+
+fn secret_or<T: FromStr>(secrets: &SecretStore, key: &str, default: T) -> T {
+    match secrets.get(key) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("Secret {key} has an invalid value: {value}")),
+        None => default,
+    }
+}
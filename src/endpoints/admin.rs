@@ -0,0 +1,36 @@
+use axum::extract::{Json, State};
+use serde_json::json;
+
+use super::auth::Claims;
+use crate::services::speech_error::SpeechError;
+use crate::services::speech_gc;
+use crate::state::AppState;
+
+/// Reports what the next speech GC sweep would delete, without deleting
+/// anything. Admins only.
+pub async fn speech_gc_report(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    require_admin(&state, &claims).await?;
+
+    let grace = state.speech_config.gc_interval;
+    let report = speech_gc::sweep(&state, grace, true)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to plan speech GC: {e}")))?;
+    Ok(Json(json!(report)))
+}
+
+async fn require_admin(state: &AppState, claims: &Claims) -> Result<(), SpeechError> {
+    let is_admin: Option<bool> = sqlx::query_scalar("SELECT is_admin FROM users WHERE id = $1")
+        .bind(claims.user_id())
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load user: {e}")))?;
+
+    if is_admin == Some(true) {
+        Ok(())
+    } else {
+        Err(SpeechError::Forbidden("Admins only".to_string()))
+    }
+}
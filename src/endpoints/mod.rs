@@ -1,6 +1,11 @@
+pub mod admin;
 pub mod auth;
 pub mod openai;
 pub mod speech;
+pub mod speech_audio;
+pub mod speech_batches;
+pub mod usage;
+pub mod webhooks;
 
 pub async fn health_check() -> &'static str {
     "Hello, world!"
@@ -1,155 +1,801 @@
-use crate::services::tts_service::call_openai_tts;
+use crate::services::speech_error::{FieldError, SpeechError};
+use crate::services::speech_events::JobEvent;
+use crate::services::speech_jobs::{self, JobState, NewSpeechJob, SpeechJob};
+use crate::services::speech_pipeline::spawn_speech_job;
+use crate::services::speech_stream::merged_audio_stream;
+use crate::services::tts_service::{TtsProvider, TTS_FORMAT, TTS_SPEED};
 use crate::state::AppState;
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
+    body::Body,
+    extract::{rejection::JsonRejection, Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
-use futures::future::join_all;
+use futures::stream::{self, Stream, StreamExt};
 use serde::Deserialize;
 use serde_json::json;
-use std::{env, fs};
-use tokio::task;
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, convert::Infallible, time::Duration};
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use uuid::Uuid;
 
 // For chunking Unicode text
-use crate::utils::chunk_text_unicode::chunk_text_unicode;
-use crate::utils::concat_mp3::concat_mp3;
+use crate::utils::chunk_text_unicode::{chunk_text_unicode, rechunk_text_unicode};
 
-// Add chrono for date/time folder naming
-use chrono::Local;
+use super::auth::Claims;
+use super::speech_audio::serve_audio;
+use super::usage::require_quota;
+
+/// Longest `Idempotency-Key` accepted.
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// How long cancelling waits for a job's chunk tasks to wind down.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Deserialize)]
 pub struct UserInput {
     pub input: String,
+    /// One of the provider's voices; defaults to its default voice.
+    pub voice: Option<String>,
+    /// One of the provider's models; defaults to its default model.
+    pub model: Option<String>,
+    /// Only `mp3` is supported, since chunks are merged as MP3.
+    pub response_format: Option<String>,
+    /// Within the provider's speed range; defaults to `TTS_SPEED`.
+    pub speed: Option<f32>,
+}
+
+impl UserInput {
+    /// Checks every field, reporting all problems at once rather than the
+    /// first.
+    fn validate(&self, tts: &dyn TtsProvider, max_chars: usize) -> Result<(), SpeechError> {
+        let errors = self.field_errors(tts, max_chars);
+        if !errors.is_empty() {
+            return Err(SpeechError::Validation(errors));
+        }
+        Ok(())
+    }
+
+    /// Everything wrong with the input; empty if it is valid.
+    pub fn field_errors(&self, tts: &dyn TtsProvider, max_chars: usize) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        validate_text(&self.input, max_chars, &mut errors);
+
+        if let Some(voice) = self.voice.as_deref() {
+            if !tts.voices().contains(&voice) {
+                let allowed = tts.voices().join(", ");
+                errors.push(FieldError::new(
+                    "voice",
+                    format!("must be one of {allowed}"),
+                ));
+            }
+        }
+        if let Some(model) = self.model.as_deref() {
+            if !tts.models().contains(&model) {
+                let allowed = tts.models().join(", ");
+                errors.push(FieldError::new(
+                    "model",
+                    format!("must be one of {allowed}"),
+                ));
+            }
+        }
+        if let Some(format) = self.response_format.as_deref() {
+            if format != TTS_FORMAT {
+                errors.push(FieldError::new(
+                    "response_format",
+                    format!("must be {TTS_FORMAT}"),
+                ));
+            }
+        }
+        if let Some(speed) = self.speed {
+            let (min, max) = tts.speed_range();
+            if !(min..=max).contains(&speed) {
+                errors.push(FieldError::new(
+                    "speed",
+                    format!("must be between {min} and {max}"),
+                ));
+            }
+        }
+        errors
+    }
+
+    /// Identifies the request for `Idempotency-Key`: two requests with the
+    /// same input and settings, defaults filled in, hash the same.
+    pub fn request_hash(&self, tts: &dyn TtsProvider) -> String {
+        let request = json!({
+            "input": self.input,
+            "voice": self.voice.as_deref().unwrap_or(tts.default_voice()),
+            "model": self.model.as_deref().unwrap_or(tts.default_model()),
+            "speed": self.speed.unwrap_or(TTS_SPEED),
+        });
+        hex::encode(Sha256::digest(request.to_string().as_bytes()))
+    }
+
+    /// The job recording this input once it has been split into `chunks`,
+    /// with defaults filled in for the settings the request left out.
+    pub fn to_job<'a>(
+        &'a self,
+        tts: &'a dyn TtsProvider,
+        user_id: i32,
+        input_hash: &'a str,
+        chunks: &'a [String],
+    ) -> NewSpeechJob<'a> {
+        NewSpeechJob {
+            user_id: Some(user_id),
+            input_hash,
+            input_chars: self.input.chars().count() as i32,
+            voice: self.voice.as_deref().unwrap_or(tts.default_voice()),
+            model: self.model.as_deref().unwrap_or(tts.default_model()),
+            speed: self.speed.unwrap_or(TTS_SPEED),
+            title: None,
+            idempotency: None,
+            chunks,
+        }
+    }
+}
+
+/// The edited document for a resubmit. The job keeps its voice, model and
+/// speed.
+#[derive(Deserialize)]
+pub struct ResubmitInput {
+    pub input: String,
+}
+
+/// Checks a document before it is chunked: it must have some text, be no
+/// longer than `max_chars` and hold no control characters other than line
+/// breaks and tabs.
+pub fn validate_text(input: &str, max_chars: usize, errors: &mut Vec<FieldError>) {
+    if input.trim().is_empty() {
+        errors.push(FieldError::new("input", "must not be empty"));
+        return;
+    }
+
+    let chars = input.chars().count();
+    if chars > max_chars {
+        errors.push(FieldError::new(
+            "input",
+            format!("is {chars} characters long; at most {max_chars} are allowed"),
+        ));
+    }
+
+    let control = input
+        .chars()
+        .enumerate()
+        .find(|(_, c)| c.is_control() && !matches!(c, '\n' | '\r' | '\t'));
+    if let Some((position, c)) = control {
+        errors.push(FieldError::new(
+            "input",
+            format!(
+                "contains control character U+{:04X} at position {position}",
+                c as u32
+            ),
+        ));
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SpeechParams {
+    /// Respond with the merged audio as it is synthesized instead of a job ID.
+    #[serde(default)]
+    pub stream: bool,
 }
 
+/// Queues a speech job and returns its ID right away. The chunk -> TTS ->
+/// merge pipeline runs in the background; poll `GET /api/speech/jobs/:id`
+/// for progress.
+///
+/// With `?stream=true` the response is instead a chunked `audio/mpeg` body
+/// that grows as chunks finish, so playback can start before the whole
+/// input is synthesized. The job is recorded either way, and its ID is
+/// sent in the `X-Speech-Job-Id` header.
+///
+/// A request with an `Idempotency-Key` header the caller already used gets
+/// the job recorded for that key, marked with `Idempotent-Replayed: true`,
+/// instead of a new one; if its payload differs from the first, it is a
+/// conflict.
 pub async fn speech(
-    State(_state): State<AppState>,
-    Json(payload): Json<UserInput>,
-) -> (StatusCode, Json<serde_json::Value>) {
+    State(state): State<AppState>,
+    claims: Claims,
+    Query(params): Query<SpeechParams>,
+    headers: HeaderMap,
+    payload: Result<Json<UserInput>, JsonRejection>,
+) -> Result<Response, SpeechError> {
+    let Json(payload) = payload?;
     println!(
         "Speech endpoint called with input length: {}",
         payload.input.len()
     );
 
-    // 1) Check OPENAI_API_KEY
-    let api_key = match env::var("OPENAI_API_KEY") {
-        Ok(k) => {
-            println!("Found OPENAI_API_KEY in environment");
-            k
-        }
-        Err(_) => {
-            println!("Error: Missing OPENAI_API_KEY environment variable");
-            let err = json!({ "error": "Missing OPENAI_API_KEY" });
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(err));
+    // 1) Validate the request before doing any work for it
+    let tts = state.tts.as_ref();
+    payload.validate(tts, state.speech_config.max_input_chars)?;
+    let idempotency_key = idempotency_key(&headers)?;
+    let request_hash = payload.request_hash(tts);
+
+    // 2) A retried submission gets its job back without being billed again
+    if let Some(key) = &idempotency_key {
+        if let Some(response) =
+            replay_submission(&state, *claims.user_id(), key, &request_hash, params.stream).await?
+        {
+            return Ok(response);
         }
-    };
+    }
 
-    // 2) Chunk text at Unicode boundaries
+    // 3) Chunk text at Unicode boundaries, small enough for the provider
     println!("Calling chunk_text_unicode...");
-    let chunks = chunk_text_unicode(&payload.input, 4096);
+    let chunks = chunk_text_unicode(&payload.input, tts.max_input_chars());
     println!("Finished chunking; got {} chunk(s)", chunks.len());
 
-    if chunks.is_empty() {
-        let err = json!({ "error": "No text provided." });
-        println!("No text => returning 400");
-        return (StatusCode::BAD_REQUEST, Json(err));
-    }
-
-    // 3) Create a folder named with the current date/time, e.g. "2025-03-21-12:25"
-    let now = Local::now();
-    let folder_name = now.format("%Y-%m-%d-%H:%M").to_string();
-    if let Err(e) = fs::create_dir_all(&folder_name) {
-        let err = json!({ "error": format!("Failed to create directory {folder_name}: {e}") });
-        println!("Error creating directory {}: {}", folder_name, e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(err));
-    }
-
-    println!("Created or verified existence of folder: {}", folder_name);
-
-    // 4) For each chunk, spawn a parallel TTS task
-    println!("Spawning parallel tasks for TTS calls...");
-    let mut tasks = Vec::new();
-    for (i, chunk) in chunks.iter().enumerate() {
-        let api_key_cloned = api_key.clone();
-        let chunk_cloned = chunk.clone();
-        let voice = "onyx".to_string();
-        let index = i + 1;
-        println!("  -> Chunk #{index}: length = {} graphemes", chunk.len());
-
-        // Construct the output path for this chunk
-        let chunk_filename = format!("{}/speech-chunk-{}.mp3", folder_name, index);
-
-        tasks.push(task::spawn(async move {
-            println!("  -> [Task {index}] calling TTS...");
-            let tts_result = call_openai_tts(&api_key_cloned, &chunk_cloned, &voice).await;
-            match tts_result {
-                Ok(bytes) => match fs::write(&chunk_filename, &bytes) {
-                    Ok(_) => {
-                        println!("  -> [Task {index}] wrote {chunk_filename}");
-                        Ok(chunk_filename)
-                    }
-                    Err(e) => {
-                        let msg = format!("Failed to write {chunk_filename}: {e}");
-                        println!("  -> [Task {index}] error: {msg}");
-                        Err(msg)
-                    }
-                },
-                Err(msg) => {
-                    let full_msg = format!("Chunk {index} TTS error: {msg}");
-                    println!("  -> [Task {index}] TTS error: {full_msg}");
-                    Err(full_msg)
-                }
+    // 4) Record the job and hand the rest of the work to a background task
+    let input_chars = payload.input.chars().count() as i64;
+    let mut tx = state.db.begin().await?;
+    require_quota(&mut tx, *claims.user_id(), input_chars).await?;
+
+    let input_hash = hex::encode(Sha256::digest(payload.input.as_bytes()));
+    let new_job = NewSpeechJob {
+        idempotency: idempotency_key
+            .as_deref()
+            .map(|key| (key, request_hash.as_str())),
+        ..payload.to_job(tts, *claims.user_id(), &input_hash, &chunks)
+    };
+    let recorded = match speech_jobs::insert_job(&mut tx, &new_job, None).await {
+        Ok(job_id) => tx.commit().await.map(|_| job_id),
+        Err(e) => {
+            drop(tx);
+            Err(e)
+        }
+    };
+    let job_id = match recorded {
+        Ok(job_id) => job_id,
+        // The same key was submitted concurrently and the other request won
+        Err(e) if is_unique_violation(&e) => {
+            let key = idempotency_key.as_deref().unwrap_or_default();
+            if let Some(response) =
+                replay_submission(&state, *claims.user_id(), key, &request_hash, params.stream)
+                    .await?
+            {
+                return Ok(response);
             }
-        }));
+            return Err(SpeechError::Internal(format!(
+                "Failed to record speech job: {e}"
+            )));
+        }
+        Err(e) => {
+            println!("Error recording speech job: {e}");
+            return Err(SpeechError::Internal(format!(
+                "Failed to record speech job: {e}"
+            )));
+        }
+    };
+
+    println!("Queued speech job {job_id}");
+    let events = spawn_speech_job(state.clone(), job_id);
+    Ok(submission_response(&state, job_id, events, params.stream))
+}
+
+/// The response to a submission of `job_id`: the merged audio as it is
+/// produced if `stream`, otherwise where to find the job.
+fn submission_response(
+    state: &AppState,
+    job_id: Uuid,
+    events: broadcast::Receiver<JobEvent>,
+    stream: bool,
+) -> Response {
+    if stream {
+        let audio = merged_audio_stream(state.db.clone(), state.storage.clone(), job_id, events);
+        return (
+            [
+                (header::CONTENT_TYPE, "audio/mpeg".to_string()),
+                (
+                    header::HeaderName::from_static("x-speech-job-id"),
+                    job_id.to_string(),
+                ),
+            ],
+            Body::from_stream(audio),
+        )
+            .into_response();
     }
 
-    // 5) Wait for all tasks
-    println!("All tasks spawned; waiting on join_all...");
-    let results = join_all(tasks).await;
-    println!("join_all completed; analyzing results...");
+    let response = json!({
+        "job_id": job_id,
+        "status_url": format!("/api/speech/jobs/{job_id}"),
+    });
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
 
-    // Accumulate the chunk filenames
-    let mut saved_files = Vec::new();
+/// Answers a repeated submission with the job already recorded for its
+/// `Idempotency-Key`, or returns `None` if there is none.
+async fn replay_submission(
+    state: &AppState,
+    user_id: i32,
+    key: &str,
+    request_hash: &str,
+    stream: bool,
+) -> Result<Option<Response>, SpeechError> {
+    let Some((job_id, recorded_hash)) =
+        speech_jobs::find_idempotent_job(&state.db, user_id, key).await?
+    else {
+        return Ok(None);
+    };
+    if recorded_hash != request_hash {
+        return Err(SpeechError::Conflict(format!(
+            "Idempotency-Key {key:?} was already used for a different request"
+        )));
+    }
 
-    for (i, result) in results.into_iter().enumerate() {
-        match result {
-            Ok(Ok(filename)) => {
-                println!("Task #{} succeeded => {}", i + 1, filename);
-                saved_files.push(filename);
-            }
-            Ok(Err(e)) => {
-                println!("Task #{} returned an error => {}", i + 1, e);
-                let err = json!({ "error": format!("Task #{} error: {e}", i+1) });
-                return (StatusCode::INTERNAL_SERVER_ERROR, Json(err));
-            }
-            Err(join_err) => {
-                println!("Task #{} panicked or cancelled => {}", i + 1, join_err);
-                let err = json!({ "error": format!("Join error on task #{}: {join_err}", i+1) });
-                return (StatusCode::INTERNAL_SERVER_ERROR, Json(err));
+    println!("Replaying speech job {job_id} for Idempotency-Key {key:?}");
+    let mut response = match state.job_events.subscribe(job_id) {
+        // Still running: follow it like the original request would have
+        Some(events) => submission_response(state, job_id, events, stream),
+        None if stream => stored_job_audio(state, job_id).await?,
+        None => submission_response(state, job_id, broadcast::channel(1).1, false),
+    };
+    response.headers_mut().insert(
+        header::HeaderName::from_static("idempotent-replayed"),
+        header::HeaderValue::from_static("true"),
+    );
+    Ok(Some(response))
+}
+
+/// The merged audio of a job that is no longer running, for a streamed
+/// replay. Jobs that didn't finish, or whose audio has been cleaned up,
+/// have none.
+async fn stored_job_audio(state: &AppState, job_id: Uuid) -> Result<Response, SpeechError> {
+    let job = speech_jobs::fetch_job(&state.db, job_id)
+        .await?
+        .ok_or_else(|| SpeechError::NotFound(format!("No speech job with id {job_id}")))?;
+    if job.state != JobState::Done {
+        return Err(SpeechError::Conflict(format!(
+            "Speech job {job_id} is {}; it has no audio to stream",
+            job.state.as_str()
+        )));
+    }
+    let merged_file = match job.merged_file {
+        Some(key) if job.expired_at.is_none() => key,
+        _ => {
+            return Err(SpeechError::NotFound(format!(
+                "The audio of speech job {job_id} has expired"
+            )))
+        }
+    };
+
+    let mut response = serve_audio(state, &merged_file, &HeaderMap::new()).await?;
+    response.headers_mut().insert(
+        header::HeaderName::from_static("x-speech-job-id"),
+        header::HeaderValue::from_str(&job_id.to_string()).unwrap(),
+    );
+    Ok(response)
+}
+
+/// The `Idempotency-Key` header, if the request has one.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, SpeechError> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key)
+            if !key.is_empty()
+                && key.len() <= MAX_IDEMPOTENCY_KEY_LEN
+                && key.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(SpeechError::Validation(vec![FieldError::new(
+            "Idempotency-Key",
+            format!("must be 1 to {MAX_IDEMPOTENCY_KEY_LEN} visible ASCII characters"),
+        )])),
+    }
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+/// Lists the caller's own speech jobs, newest first.
+pub async fn list_speech_jobs(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let jobs = speech_jobs::list_user_jobs(&state.db, *claims.user_id())
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to list speech jobs: {e}")))?;
+    Ok(Json(json!({ "jobs": jobs })))
+}
+
+pub async fn speech_job_status(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let job = load_job(&state, &claims, job_id).await?;
+    Ok(Json(json!(job)))
+}
+
+/// Streams a job's progress as Server-Sent Events. The first event is a
+/// `job-status` snapshot of the job as stored; after that, the pipeline's
+/// own events follow until `job-complete`. For a job that is not running
+/// the stream ends after the snapshot.
+pub async fn speech_job_events(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+) -> Result<Response, SpeechError> {
+    // Subscribe before reading the snapshot so no event falls in between
+    let live = state.job_events.subscribe(job_id);
+    let job = load_job(&state, &claims, job_id).await?;
+
+    let snapshot = Event::default()
+        .event("job-status")
+        .json_data(&job)
+        .unwrap();
+    let events = stream::once(async { snapshot }).chain(live_events(live));
+
+    Ok(Sse::new(events.map(Ok::<_, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+/// Turns a job's broadcast receiver into SSE events, skipping over any the
+/// client was too slow to receive.
+fn live_events(rx: Option<broadcast::Receiver<JobEvent>>) -> impl Stream<Item = Event> {
+    stream::unfold(rx, |rx| async move {
+        let mut rx = rx?;
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let sse = Event::default()
+                        .event(event.name())
+                        .json_data(&event)
+                        .unwrap();
+                    return Some((sse, Some(rx)));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
             }
         }
+    })
+}
+
+/// Re-synthesizes only the chunks of a failed or cancelled job that are
+/// missing, then merges. Chunks that already succeeded are not paid for again.
+pub async fn resume_speech_job(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+) -> Result<(StatusCode, Json<serde_json::Value>), SpeechError> {
+    load_job(&state, &claims, job_id).await?;
+
+    // Only the chunks that haven't finished will be sent again
+    let remaining: i64 = speech_jobs::fetch_chunk_work(&state.db, job_id)
+        .await?
+        .iter()
+        .filter(|c| c.done_key().is_none())
+        .map(|c| c.text.as_deref().map_or(0, |t| t.chars().count()) as i64)
+        .sum();
+    let mut tx = state.db.begin().await?;
+    require_quota(&mut tx, *claims.user_id(), remaining).await?;
+
+    if !speech_jobs::requeue_job(&mut *tx, job_id).await? {
+        return Err(SpeechError::Conflict(format!(
+            "Speech job {job_id} has neither failed nor been cancelled"
+        )));
     }
+    tx.commit().await?;
 
-    // 6) Now we do a naive merge of those chunk MP3s
-    let final_mp3_path = format!("{}/speech-merged.mp3", folder_name);
-    println!(
-        "Merging {} chunk(s) => {}",
-        saved_files.len(),
-        final_mp3_path
+    println!("Resuming speech job {job_id}");
+    spawn_speech_job(state.clone(), job_id);
+
+    let response = json!({
+        "job_id": job_id,
+        "status_url": format!("/api/speech/jobs/{job_id}"),
+    });
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+/// Replaces a finished or failed job's input with an edited version of the
+/// document and runs it again. The new input is chunked around the job's
+/// current chunks and compared with them: only chunks whose text changed
+/// are synthesized, and the merged audio is rebuilt from old and new ones.
+pub async fn resubmit_speech_job(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+    payload: Result<Json<ResubmitInput>, JsonRejection>,
+) -> Result<(StatusCode, Json<serde_json::Value>), SpeechError> {
+    let Json(payload) = payload?;
+    let mut errors = Vec::new();
+    validate_text(
+        &payload.input,
+        state.speech_config.max_input_chars,
+        &mut errors,
     );
-    let saved_files_ref: Vec<&str> = saved_files.iter().map(|s| s.as_str()).collect();
-    if let Err(e) = concat_mp3(&saved_files_ref, &final_mp3_path) {
-        println!("Error merging MP3: {}", e);
-        let err = json!({ "error": format!("Failed to merge mp3: {e}") });
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(err));
+    if !errors.is_empty() {
+        return Err(SpeechError::Validation(errors));
     }
 
-    // 7) Return success
-    println!("All chunks processed + merged => {}", final_mp3_path);
+    load_job(&state, &claims, job_id).await?;
+
+    let previous = speech_jobs::fetch_chunk_work(&state.db, job_id).await?;
+    let previous_texts: Vec<String> = previous
+        .iter()
+        .map(|c| c.text.clone().unwrap_or_default())
+        .collect();
+    let chunks = rechunk_text_unicode(&payload.input, &previous_texts, state.tts.max_input_chars());
+
+    // Only chunks without finished audio to carry over will be sent
+    let finished: HashSet<&str> = previous
+        .iter()
+        .filter(|c| c.done_key().is_some())
+        .filter_map(|c| c.text.as_deref())
+        .collect();
+    let changed: i64 = chunks
+        .iter()
+        .filter(|c| !finished.contains(c.as_str()))
+        .map(|c| c.chars().count() as i64)
+        .sum();
+    let mut tx = state.db.begin().await?;
+    require_quota(&mut tx, *claims.user_id(), changed).await?;
+
+    let input_hash = hex::encode(Sha256::digest(payload.input.as_bytes()));
+    let input_chars = payload.input.chars().count() as i32;
+    let revision = speech_jobs::revise_job(&mut tx, job_id, &input_hash, input_chars, &chunks)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to resubmit speech job: {e}")))?
+        .ok_or_else(|| SpeechError::Conflict(format!("Speech job {job_id} is still running")))?;
+    tx.commit()
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to resubmit speech job: {e}")))?;
+
+    // The previous merged audio and any dropped chunks are no longer referenced
+    for key in &revision.stale_keys {
+        if let Err(e) = state.storage.delete(key).await {
+            println!("Failed to delete stale audio {key} of speech job {job_id}: {e}");
+        }
+    }
+
+    println!(
+        "Resubmitted speech job {job_id} as revision {}: {} chunk(s) reused, {} to synthesize",
+        revision.revision, revision.chunks_reused, revision.chunks_pending
+    );
+    spawn_speech_job(state.clone(), job_id);
+
     let response = json!({
-        "message": "All chunks processed in parallel and merged",
-        "files": saved_files,
-        "merged_file": final_mp3_path,
+        "job_id": job_id,
+        "revision": revision.revision,
+        "chunks_reused": revision.chunks_reused,
+        "chunks_pending": revision.chunks_pending,
+        "status_url": format!("/api/speech/jobs/{job_id}"),
     });
-    (StatusCode::OK, Json(response))
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+#[derive(Deserialize)]
+pub struct CancelParams {
+    /// Keep the audio of chunks that already finished, so that resuming the
+    /// job later doesn't pay for them again.
+    #[serde(default)]
+    pub keep_chunks: bool,
+}
+
+/// Stops a queued or running job. Chunks being synthesized are aborted and
+/// anything they stored is deleted; so is the audio of finished chunks,
+/// unless `?keep_chunks=true`. The job can be resumed later.
+pub async fn cancel_speech_job(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+    Query(params): Query<CancelParams>,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    load_job(&state, &claims, job_id).await?;
+
+    // Subscribe first, so the pipeline can't stop unnoticed in between
+    let live = state.job_events.subscribe(job_id);
+    if !speech_jobs::cancel_job(&state.db, job_id).await? {
+        return Err(SpeechError::Conflict(format!(
+            "Speech job {job_id} is not queued or running"
+        )));
+    }
+
+    println!("Cancelling speech job {job_id}");
+    if state.job_events.cancel(job_id) {
+        if let Some(live) = live {
+            if timeout(CANCEL_TIMEOUT, wait_for_completion(live))
+                .await
+                .is_err()
+            {
+                println!("Speech job {job_id} did not stop within {CANCEL_TIMEOUT:?}");
+            }
+        }
+    }
+
+    // With the pipeline stopped, nothing writes to the job's chunks anymore
+    let discard: Vec<String> = speech_jobs::fetch_chunk_work(&state.db, job_id)
+        .await?
+        .into_iter()
+        .filter(|c| !(params.keep_chunks && c.done_key().is_some()))
+        .filter_map(|c| c.object_key)
+        .collect();
+    for key in &discard {
+        if let Err(e) = state.storage.delete(key).await {
+            println!("Failed to delete {key} of cancelled speech job {job_id}: {e}");
+        }
+    }
+    speech_jobs::reset_chunks(&state.db, job_id, params.keep_chunks).await?;
+
+    let job = load_job(&state, &claims, job_id).await?;
+    Ok(Json(json!(job)))
+}
+
+/// Waits until a job's pipeline reports that it stopped.
+async fn wait_for_completion(mut rx: broadcast::Receiver<JobEvent>) {
+    loop {
+        match rx.recv().await {
+            Ok(JobEvent::JobComplete { .. }) | Err(broadcast::error::RecvError::Closed) => return,
+            _ => continue,
+        }
+    }
+}
+
+/// Loads a job owned by the caller. Anyone else's job is a 404, so that its
+/// ID isn't confirmed.
+pub async fn load_job(
+    state: &AppState,
+    claims: &Claims,
+    job_id: Uuid,
+) -> Result<SpeechJob, SpeechError> {
+    speech_jobs::fetch_job_for_user(&state.db, job_id, *claims.user_id())
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load speech job: {e}")))?
+        .ok_or_else(|| SpeechError::NotFound(format!("No speech job with id {job_id}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::mock_tts::MockTts;
+    use axum::http::HeaderValue;
+    use std::time::Duration;
+
+    fn input(text: &str) -> UserInput {
+        UserInput {
+            input: text.to_string(),
+            voice: None,
+            model: None,
+            response_format: None,
+            speed: None,
+        }
+    }
+
+    fn fields(input: &UserInput) -> Vec<String> {
+        let tts = MockTts::new(Duration::ZERO, 0.0, 0.0);
+        input
+            .field_errors(&tts, 20)
+            .into_iter()
+            .map(|e| e.field)
+            .collect()
+    }
+
+    fn text_errors(text: &str) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        validate_text(text, 20, &mut errors);
+        errors
+    }
+
+    #[test]
+    fn valid_input_has_no_errors() {
+        assert!(fields(&input("Hello there.")).is_empty());
+        let full = UserInput {
+            voice: Some("nova".to_string()),
+            model: Some("tts-1-hd".to_string()),
+            response_format: Some("mp3".to_string()),
+            speed: Some(4.0),
+            ..input("One,\r\n\ttwo.")
+        };
+        assert!(fields(&full).is_empty());
+    }
+
+    #[test]
+    fn text_must_be_present_short_and_printable() {
+        assert_eq!(text_errors("   \n ")[0].message, "must not be empty");
+        assert_eq!(
+            text_errors(&"a".repeat(21))[0].message,
+            "is 21 characters long; at most 20 are allowed"
+        );
+        // Counted in characters, not bytes
+        assert!(text_errors(&"é".repeat(20)).is_empty());
+
+        assert_eq!(
+            text_errors("bell\u{7}")[0].message,
+            "contains control character U+0007 at position 4"
+        );
+        assert_eq!(text_errors("nul\0").len(), 1);
+        assert_eq!(text_errors("del\u{7f}").len(), 1);
+    }
+
+    #[test]
+    fn settings_must_be_supported() {
+        let cases = [
+            (
+                "voice",
+                UserInput {
+                    voice: Some("bob".to_string()),
+                    ..input("Hi.")
+                },
+            ),
+            (
+                "model",
+                UserInput {
+                    model: Some("tts-2".to_string()),
+                    ..input("Hi.")
+                },
+            ),
+            (
+                "response_format",
+                UserInput {
+                    response_format: Some("wav".to_string()),
+                    ..input("Hi.")
+                },
+            ),
+            (
+                "speed",
+                UserInput {
+                    speed: Some(0.2),
+                    ..input("Hi.")
+                },
+            ),
+            (
+                "speed",
+                UserInput {
+                    speed: Some(4.5),
+                    ..input("Hi.")
+                },
+            ),
+        ];
+        for (field, input) in cases {
+            assert_eq!(fields(&input), [field]);
+        }
+    }
+
+    #[test]
+    fn every_bad_field_is_reported() {
+        let input = UserInput {
+            voice: Some("bob".to_string()),
+            response_format: Some("wav".to_string()),
+            speed: Some(0.0),
+            ..input(&"a".repeat(25))
+        };
+        assert_eq!(
+            fields(&input),
+            ["input", "voice", "response_format", "speed"]
+        );
+    }
+
+    fn key_of(value: &str) -> Result<Option<String>, SpeechError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("idempotency-key", HeaderValue::from_str(value).unwrap());
+        idempotency_key(&headers)
+    }
+
+    #[test]
+    fn idempotency_key_must_be_visible_ascii() {
+        assert_eq!(idempotency_key(&HeaderMap::new()).unwrap(), None);
+        assert_eq!(
+            key_of("order-42/retry_1").unwrap().as_deref(),
+            Some("order-42/retry_1")
+        );
+
+        for bad in ["", " ", "   ", "\t", "two words", " padded"] {
+            assert!(
+                matches!(key_of(bad), Err(SpeechError::Validation(_))),
+                "{bad:?}"
+            );
+        }
+        let too_long = "k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1);
+        assert!(key_of(&too_long).is_err());
+    }
 }
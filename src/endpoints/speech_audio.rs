@@ -0,0 +1,137 @@
+use super::auth::Claims;
+use super::speech::load_job;
+use crate::services::speech_error::SpeechError;
+use crate::state::AppState;
+use crate::utils::byte_range::{parse_range, ByteRange};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// Serves a job's merged MP3, with `Range` support so players can seek.
+pub async fn speech_job_audio(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, SpeechError> {
+    let job = load_job(&state, &claims, job_id).await?;
+
+    match job.merged_file {
+        Some(path) => serve_audio(&state, &path, &headers).await,
+        None => Err(SpeechError::NotFound(format!(
+            "Speech job {job_id} has no merged audio yet"
+        ))),
+    }
+}
+
+/// Serves a single chunk's MP3, with `Range` support.
+pub async fn speech_chunk_audio(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path((job_id, index)): Path<(Uuid, i32)>,
+    headers: HeaderMap,
+) -> Result<Response, SpeechError> {
+    let job = load_job(&state, &claims, job_id).await?;
+
+    let file = job
+        .chunks
+        .into_iter()
+        .find(|c| c.index == index)
+        .and_then(|c| c.file);
+
+    match file {
+        Some(path) => serve_audio(&state, &path, &headers).await,
+        None => Err(SpeechError::NotFound(format!(
+            "Chunk {index} of speech job {job_id} has no audio"
+        ))),
+    }
+}
+
+/// Streams an MP3 from storage with `Content-Length`, `ETag` and single
+/// byte range support. Keys that are missing from storage, or that could
+/// reach outside it, are a 404.
+pub async fn serve_audio(
+    state: &AppState,
+    key: &str,
+    headers: &HeaderMap,
+) -> Result<Response, SpeechError> {
+    let info = match state.storage.head(key).await {
+        Ok(info) => info,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::InvalidInput) => {
+            println!("Cannot serve audio {key}: {e}");
+            return Err(SpeechError::NotFound(
+                "Audio file no longer exists".to_string(),
+            ));
+        }
+        Err(e) => return Err(read_error(e)),
+    };
+
+    let len = info.size;
+    let etag = info.etag;
+
+    if header_str(headers, header::IF_NONE_MATCH).is_some_and(|tags| etag_matches(tags, &etag)) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    // A range only applies if the client's copy is still current
+    let range_header = match header_str(headers, header::IF_RANGE) {
+        Some(tag) if tag != etag => None,
+        _ => header_str(headers, header::RANGE),
+    };
+
+    let (status, start, count) = match parse_range(range_header, len) {
+        ByteRange::Full => (StatusCode::OK, 0, len),
+        ByteRange::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+            )
+                .into_response());
+        }
+    };
+
+    let body = if count == 0 {
+        Body::empty()
+    } else {
+        match state.storage.get_range(key, start..start + count).await {
+            Ok(stream) => Body::from_stream(stream),
+            Err(e) => return Err(read_error(e)),
+        }
+    };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "audio/mpeg")
+        .header(header::CONTENT_LENGTH, count)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag);
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {start}-{}/{len}", start + count - 1),
+        );
+    }
+
+    Ok(response.body(body).unwrap())
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn read_error(e: std::io::Error) -> SpeechError {
+    SpeechError::Storage(format!("Failed to read audio: {e}"))
+}
@@ -0,0 +1,188 @@
+use crate::services::speech_batches;
+use crate::services::speech_error::{FieldError, SpeechError};
+use crate::services::speech_jobs::NewSpeechJob;
+use crate::services::speech_pipeline::spawn_speech_job;
+use crate::services::tts_service::TtsProvider;
+use crate::state::AppState;
+use axum::{
+    extract::{rejection::JsonRejection, Json, Path, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::utils::chunk_text_unicode::chunk_text_unicode;
+
+use super::auth::Claims;
+use super::speech::UserInput;
+use super::usage::require_quota;
+
+/// Most documents a single batch may hold.
+const MAX_BATCH_DOCUMENTS: usize = 100;
+/// Longest document title, in characters.
+const MAX_TITLE_CHARS: usize = 200;
+
+#[derive(Deserialize)]
+pub struct BatchInput {
+    pub documents: Vec<BatchDocument>,
+}
+
+/// One document of a batch: a title plus everything `POST /api/speech`
+/// accepts.
+#[derive(Deserialize)]
+pub struct BatchDocument {
+    pub title: String,
+    #[serde(flatten)]
+    pub speech: UserInput,
+}
+
+impl BatchInput {
+    /// Checks every document, naming fields by their path, as in
+    /// `documents[2].voice`.
+    fn validate(&self, tts: &dyn TtsProvider, max_chars: usize) -> Result<(), SpeechError> {
+        let mut errors = Vec::new();
+
+        let count = self.documents.len();
+        if count == 0 {
+            errors.push(FieldError::new("documents", "must not be empty"));
+        } else if count > MAX_BATCH_DOCUMENTS {
+            errors.push(FieldError::new(
+                "documents",
+                format!("has {count} documents; at most {MAX_BATCH_DOCUMENTS} are allowed"),
+            ));
+        }
+
+        for (i, document) in self.documents.iter().enumerate() {
+            let title = &document.title;
+            let title_error = if title.trim().is_empty() {
+                Some("must not be empty".to_string())
+            } else if title.chars().count() > MAX_TITLE_CHARS {
+                Some(format!("must be at most {MAX_TITLE_CHARS} characters long"))
+            } else if title.chars().any(char::is_control) {
+                Some("must not contain control characters".to_string())
+            } else {
+                None
+            };
+            if let Some(message) = title_error {
+                errors.push(FieldError::new(format!("documents[{i}].title"), message));
+            }
+
+            for mut error in document.speech.field_errors(tts, max_chars) {
+                error.field = format!("documents[{i}].{}", error.field);
+                errors.push(error);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(SpeechError::Validation(errors));
+        }
+        Ok(())
+    }
+}
+
+/// Queues one speech job per document under a new batch ID and returns
+/// right away. The jobs run like any other; the batch only groups them for
+/// `GET /api/speech/batches/:id` and its manifest.
+pub async fn create_speech_batch(
+    State(state): State<AppState>,
+    claims: Claims,
+    payload: Result<Json<BatchInput>, JsonRejection>,
+) -> Result<(StatusCode, Json<serde_json::Value>), SpeechError> {
+    let Json(payload) = payload?;
+    println!(
+        "Batch endpoint called with {} document(s)",
+        payload.documents.len()
+    );
+
+    let tts = state.tts.as_ref();
+    payload.validate(tts, state.speech_config.max_input_chars)?;
+
+    // The whole batch has to fit in the quota, not just its first documents
+    let total_chars: i64 = payload
+        .documents
+        .iter()
+        .map(|d| d.speech.input.chars().count() as i64)
+        .sum();
+    let mut tx = state.db.begin().await?;
+    require_quota(&mut tx, *claims.user_id(), total_chars).await?;
+
+    let prepared: Vec<(String, Vec<String>)> = payload
+        .documents
+        .iter()
+        .map(|d| {
+            let input_hash = hex::encode(Sha256::digest(d.speech.input.as_bytes()));
+            (
+                input_hash,
+                chunk_text_unicode(&d.speech.input, tts.max_input_chars()),
+            )
+        })
+        .collect();
+    let new_jobs: Vec<NewSpeechJob> = payload
+        .documents
+        .iter()
+        .zip(&prepared)
+        .map(|(d, (input_hash, chunks))| NewSpeechJob {
+            title: Some(&d.title),
+            ..d.speech.to_job(tts, *claims.user_id(), input_hash, chunks)
+        })
+        .collect();
+
+    let recorded = match speech_batches::create_batch(&mut tx, *claims.user_id(), &new_jobs).await {
+        Ok(created) => tx.commit().await.map(|_| created),
+        Err(e) => Err(e),
+    };
+    let (batch_id, job_ids) = recorded.map_err(|e| {
+        println!("Error recording speech batch: {e}");
+        SpeechError::Internal(format!("Failed to record speech batch: {e}"))
+    })?;
+
+    println!(
+        "Queued speech batch {batch_id} with {} job(s)",
+        job_ids.len()
+    );
+    for job_id in &job_ids {
+        spawn_speech_job(state.clone(), *job_id);
+    }
+
+    let response = json!({
+        "batch_id": batch_id,
+        "job_ids": job_ids,
+        "status_url": format!("/api/speech/batches/{batch_id}"),
+        "manifest_url": format!("/api/speech/batches/{batch_id}/manifest"),
+    });
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+/// Aggregate progress of a batch, followed by each of its jobs.
+pub async fn speech_batch_status(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(batch_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let batch = load_batch(&state, &claims, batch_id).await?;
+    Ok(Json(json!(batch)))
+}
+
+/// Lists the merged output of every document in a batch, in submission
+/// order. Documents that haven't finished are listed without one.
+pub async fn speech_batch_manifest(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(batch_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let batch = load_batch(&state, &claims, batch_id).await?;
+    Ok(Json(json!(batch.manifest())))
+}
+
+async fn load_batch(
+    state: &AppState,
+    claims: &Claims,
+    batch_id: Uuid,
+) -> Result<speech_batches::SpeechBatch, SpeechError> {
+    speech_batches::fetch_batch_for_user(&state.db, batch_id, *claims.user_id())
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load speech batch: {e}")))?
+        .ok_or_else(|| SpeechError::NotFound(format!("No speech batch with id {batch_id}")))
+}
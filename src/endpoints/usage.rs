@@ -0,0 +1,58 @@
+use axum::extract::{Json, State};
+use serde_json::json;
+use sqlx::PgConnection;
+
+use super::auth::Claims;
+use crate::services::speech_error::SpeechError;
+use crate::services::usage;
+use crate::state::AppState;
+
+/// Reports the caller's TTS character usage and what is left of their quota
+/// in the current billing period.
+pub async fn usage_report(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let usage = usage::usage_for_user(&state.db, *claims.user_id())
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load usage: {e}")))?;
+    Ok(Json(json!(usage)))
+}
+
+/// Refuses with 402 when synthesizing `chars` more characters would take the
+/// user past their monthly quota. Characters of their other unfinished jobs
+/// count as already spent.
+///
+/// The user's row stays locked until `tx` ends, so `tx` has to be the
+/// transaction that records the work; otherwise two requests could both
+/// pass the check before either reserves its characters.
+pub async fn require_quota(
+    tx: &mut PgConnection,
+    user_id: i32,
+    chars: i64,
+) -> Result<(), SpeechError> {
+    usage::lock_user(tx, user_id)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to lock user: {e}")))?;
+    let usage = usage::usage_for_user(tx, user_id)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load usage: {e}")))?;
+
+    if chars <= usage.chars_remaining {
+        return Ok(());
+    }
+
+    println!(
+        "User {user_id} is over quota: {chars} chars requested, {} of {} left",
+        usage.chars_remaining, usage.chars_limit
+    );
+    Err(SpeechError::QuotaExceeded {
+        message: format!(
+            "Monthly character quota exceeded: this needs {chars} characters but only {} of {} are left until {}",
+            usage.chars_remaining,
+            usage.chars_limit,
+            usage.period_end.to_rfc3339()
+        ),
+        details: json!(usage),
+    })
+}
@@ -0,0 +1,106 @@
+use crate::services::speech_error::{FieldError, SpeechError};
+use crate::services::webhooks;
+use crate::state::AppState;
+use axum::{
+    extract::{rejection::JsonRejection, Json, Path, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use super::auth::Claims;
+
+#[derive(Deserialize)]
+pub struct WebhookInput {
+    pub url: String,
+}
+
+/// Registers a URL to be sent a signed POST whenever one of the caller's
+/// speech jobs finishes or fails. The response is the only place the
+/// signing secret is shown. URLs that resolve to loopback, private or
+/// link-local addresses are refused unless `SPEECH_WEBHOOK_ALLOW_PRIVATE`
+/// allows the first two.
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    claims: Claims,
+    payload: Result<Json<WebhookInput>, JsonRejection>,
+) -> Result<(StatusCode, Json<serde_json::Value>), SpeechError> {
+    let Json(payload) = payload?;
+    let allow_private = state.speech_config.webhook_allow_private;
+    if let Err(message) = webhooks::resolve_url(&payload.url, allow_private).await {
+        return Err(SpeechError::Validation(vec![FieldError::new(
+            "url", message,
+        )]));
+    }
+
+    let (webhook, secret) = webhooks::create_webhook(&state.db, *claims.user_id(), &payload.url)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to create webhook: {e}")))?;
+
+    println!(
+        "Registered webhook {} for user {}",
+        webhook.id,
+        claims.user_id()
+    );
+    let response = json!({
+        "id": webhook.id,
+        "url": webhook.url,
+        "secret": secret,
+        "created_at": webhook.created_at,
+    });
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let webhooks = webhooks::list_webhooks(&state.db, *claims.user_id())
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to list webhooks: {e}")))?;
+    Ok(Json(json!({ "webhooks": webhooks })))
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<StatusCode, SpeechError> {
+    if !webhooks::delete_webhook(&state.db, webhook_id, *claims.user_id()).await? {
+        return Err(not_found(webhook_id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The delivery log of a webhook, newest first.
+pub async fn webhook_deliveries(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, SpeechError> {
+    let deliveries = webhooks::list_deliveries(&state.db, webhook_id, *claims.user_id())
+        .await?
+        .ok_or_else(|| not_found(webhook_id))?;
+    Ok(Json(json!({ "deliveries": deliveries })))
+}
+
+/// Sends a `ping` delivery, to check that the receiving end is reachable
+/// and verifies signatures.
+pub async fn test_webhook(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<(StatusCode, Json<serde_json::Value>), SpeechError> {
+    let delivery_id = webhooks::send_ping(&state, webhook_id, *claims.user_id())
+        .await?
+        .ok_or_else(|| not_found(webhook_id))?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "delivery_id": delivery_id })),
+    ))
+}
+
+fn not_found(webhook_id: Uuid) -> SpeechError {
+    SpeechError::NotFound(format!("No webhook with id {webhook_id}"))
+}
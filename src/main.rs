@@ -1,18 +1,24 @@
 use axum::{
     http::{
-        header::{ACCEPT, AUTHORIZATION},
+        header::{HeaderName, ACCEPT, AUTHORIZATION},
         Method,
     },
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 
+pub mod config;
 pub mod endpoints;
 pub mod services;
 pub mod state;
 pub mod utils;
 
-use crate::endpoints::speech::speech;
+use crate::config::SpeechConfig;
+use crate::endpoints::speech::{
+    cancel_speech_job, list_speech_jobs, resubmit_speech_job, resume_speech_job, speech,
+    speech_job_events, speech_job_status,
+};
+use crate::services::{speech_gc, speech_jobs, webhooks};
 use shuttle_openai::async_openai::{config::OpenAIConfig, Client};
 use shuttle_runtime::DeploymentMetadata;
 use shuttle_runtime::SecretStore;
@@ -29,15 +35,21 @@ async fn main(
     #[shuttle_runtime::Metadata] metadata: DeploymentMetadata,
     #[shuttle_runtime::Secrets] secrets: SecretStore,
 ) -> shuttle_axum::ShuttleAxum {
-    let state = AppState::new(conn, openai)
+    let speech_config = SpeechConfig::from_secrets(&secrets);
+
+    let state = AppState::new(conn, openai, speech_config)
         .await
         .map_err(|e| format!("Could not create application state: {e}"))
         .unwrap();
 
     state.seed().await;
-
-    let openai_api_key = secrets.get("OPENAI_API_KEY").unwrap();
-    std::env::set_var("OPENAI_API_KEY", &openai_api_key);
+    match speech_jobs::fail_interrupted_jobs(&state.db).await {
+        Ok(0) => {}
+        Ok(n) => println!("Marked {n} speech job(s) interrupted by the last shutdown as failed"),
+        Err(e) => println!("Failed to mark interrupted speech jobs: {e}"),
+    }
+    speech_gc::spawn_sweeper(state.clone(), state.speech_config.gc_interval);
+    webhooks::resume_pending_deliveries(&state).await;
 
     let origin = if metadata.env == shuttle_runtime::Environment::Deployment {
         format!("{}.shuttle.app", metadata.project_name)
@@ -48,8 +60,12 @@ async fn main(
     let cors = CorsLayer::new()
         .allow_credentials(true)
         .allow_origin(vec![origin.parse().unwrap()])
-        .allow_headers(vec![AUTHORIZATION, ACCEPT])
-        .allow_methods(vec![Method::GET, Method::POST]);
+        .allow_headers(vec![
+            AUTHORIZATION,
+            ACCEPT,
+            HeaderName::from_static("idempotency-key"),
+        ])
+        .allow_methods(vec![Method::GET, Method::POST, Method::DELETE]);
 
     let router = Router::new()
         .route("/api/health", get(endpoints::health_check))
@@ -59,7 +75,56 @@ async fn main(
             "/api/chat/conversations",
             get(endpoints::openai::get_conversation_list),
         )
+        .route(
+            "/api/admin/speech/gc",
+            get(endpoints::admin::speech_gc_report),
+        )
         .route("/api/speech", post(speech))
+        .route("/api/speech/jobs", get(list_speech_jobs))
+        .route(
+            "/api/speech/jobs/:id",
+            get(speech_job_status).delete(cancel_speech_job),
+        )
+        .route(
+            "/api/speech/jobs/:id/audio",
+            get(endpoints::speech_audio::speech_job_audio),
+        )
+        .route(
+            "/api/speech/jobs/:id/chunks/:index/audio",
+            get(endpoints::speech_audio::speech_chunk_audio),
+        )
+        .route("/api/speech/jobs/:id/events", get(speech_job_events))
+        .route("/api/speech/jobs/:id/resume", post(resume_speech_job))
+        .route("/api/speech/jobs/:id/resubmit", post(resubmit_speech_job))
+        .route(
+            "/api/speech/batches",
+            post(endpoints::speech_batches::create_speech_batch),
+        )
+        .route(
+            "/api/speech/batches/:id",
+            get(endpoints::speech_batches::speech_batch_status),
+        )
+        .route(
+            "/api/speech/batches/:id/manifest",
+            get(endpoints::speech_batches::speech_batch_manifest),
+        )
+        .route(
+            "/api/webhooks",
+            get(endpoints::webhooks::list_webhooks).post(endpoints::webhooks::create_webhook),
+        )
+        .route(
+            "/api/webhooks/:id",
+            delete(endpoints::webhooks::delete_webhook),
+        )
+        .route(
+            "/api/webhooks/:id/deliveries",
+            get(endpoints::webhooks::webhook_deliveries),
+        )
+        .route(
+            "/api/webhooks/:id/test",
+            post(endpoints::webhooks::test_webhook),
+        )
+        .route("/api/usage", get(endpoints::usage::usage_report))
         .route(
             "/api/chat/conversations/:id",
             get(endpoints::openai::fetch_conversation_messages)
@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// A concurrency limit that adapts with additive-increase/multiplicative-decrease.
+///
+/// Every success counts towards raising the limit by one; after `limit`
+/// successes in a row (one full "window") the limit grows. An overload
+/// signal (HTTP 429 or 5xx from the provider) halves the limit. Requests
+/// that were already in flight when the limit was cut report on the old
+/// window and do not halve it again, so one burst of 429s only backs off once.
+///
+/// Free slots are handed out fairly rather than first come, first served:
+/// round-robin across the users with requests waiting, and for each user
+/// round-robin across their jobs. A user with a 1,000-chunk job and one
+/// with a 3-chunk job alternate, so the small job finishes promptly.
+pub struct AdaptiveLimiter {
+    state: Mutex<LimiterState>,
+    min: usize,
+    max: usize,
+}
+
+struct LimiterState {
+    limit: usize,
+    in_flight: usize,
+    successes: usize,
+    /// Bumped on every decrease; permits remember the window they started in.
+    generation: u64,
+    waiting: FairQueue,
+}
+
+/// Requests waiting for a slot, grouped by user and then by job, each
+/// group in round-robin order. A waiter is woken with the generation its
+/// slot was granted in.
+#[derive(Default)]
+struct FairQueue {
+    users: VecDeque<UserQueue>,
+}
+
+struct UserQueue {
+    user_id: Option<i32>,
+    jobs: VecDeque<JobQueue>,
+}
+
+struct JobQueue {
+    job_id: Uuid,
+    waiters: VecDeque<oneshot::Sender<u64>>,
+}
+
+impl FairQueue {
+    fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    fn push(&mut self, user_id: Option<i32>, job_id: Uuid, waiter: oneshot::Sender<u64>) {
+        let user = match self.users.iter_mut().position(|u| u.user_id == user_id) {
+            Some(i) => &mut self.users[i],
+            None => {
+                self.users.push_back(UserQueue {
+                    user_id,
+                    jobs: VecDeque::new(),
+                });
+                self.users.back_mut().unwrap()
+            }
+        };
+        match user.jobs.iter_mut().find(|j| j.job_id == job_id) {
+            Some(job) => job.waiters.push_back(waiter),
+            None => user.jobs.push_back(JobQueue {
+                job_id,
+                waiters: VecDeque::from([waiter]),
+            }),
+        }
+    }
+
+    /// The next waiter in turn. Its job and user move to the back of their
+    /// queues, or leave them once they have nobody else waiting.
+    fn pop(&mut self) -> Option<oneshot::Sender<u64>> {
+        let mut user = self.users.pop_front()?;
+        let mut job = user.jobs.pop_front()?;
+        let waiter = job.waiters.pop_front();
+
+        if !job.waiters.is_empty() {
+            user.jobs.push_back(job);
+        }
+        if !user.jobs.is_empty() {
+            self.users.push_back(user);
+        }
+        waiter
+    }
+}
+
+impl AdaptiveLimiter {
+    pub fn new(initial: usize, min: usize, max: usize) -> Self {
+        assert!(
+            0 < min && min <= max,
+            "invalid limiter bounds {min}..={max}"
+        );
+
+        Self {
+            state: Mutex::new(LimiterState {
+                limit: initial.clamp(min, max),
+                in_flight: 0,
+                successes: 0,
+                generation: 0,
+                waiting: FairQueue::default(),
+            }),
+            min,
+            max,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Waits until fewer than `limit` permits are held and it is the turn
+    /// of `user_id`'s job `job_id`.
+    pub async fn acquire(self: &Arc<Self>, user_id: Option<i32>, job_id: Uuid) -> LimiterPermit {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            // Only skip the queue when nobody is in it
+            if state.in_flight < state.limit && state.waiting.is_empty() {
+                state.in_flight += 1;
+                return LimiterPermit {
+                    limiter: self.clone(),
+                    generation: state.generation,
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            state.waiting.push(user_id, job_id, tx);
+            rx
+        };
+
+        let mut waiter = Waiter {
+            rx: Some(rx),
+            limiter: self,
+        };
+        // The sender is only dropped after sending, or once the receiver is
+        // gone, so this cannot fail
+        let generation = waiter.rx.as_mut().unwrap().await.unwrap();
+        waiter.rx = None;
+
+        LimiterPermit {
+            limiter: self.clone(),
+            generation,
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        Self::dispatch(&mut state);
+    }
+
+    /// Hands free slots to waiters in turn. A waiter that gave up before
+    /// its turn came is skipped without taking a slot.
+    fn dispatch(state: &mut LimiterState) {
+        while state.in_flight < state.limit {
+            let Some(waiter) = state.waiting.pop() else {
+                return;
+            };
+            if waiter.send(state.generation).is_ok() {
+                state.in_flight += 1;
+            }
+        }
+    }
+
+    fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.successes += 1;
+
+        if state.successes >= state.limit && state.limit < self.max {
+            state.limit += 1;
+            state.successes = 0;
+            println!("TTS concurrency raised to {}", state.limit);
+            Self::dispatch(&mut state);
+        }
+    }
+
+    fn on_overload(&self, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.successes = 0;
+
+        if generation == state.generation {
+            state.limit = (state.limit / 2).max(self.min);
+            state.generation += 1;
+            println!("TTS concurrency lowered to {}", state.limit);
+        }
+    }
+}
+
+/// A request waiting in [`AdaptiveLimiter::acquire`]. If it is dropped, say
+/// because its job was cancelled, after a slot was sent its way but before
+/// it saw it, the slot is released again.
+struct Waiter<'a> {
+    rx: Option<oneshot::Receiver<u64>>,
+    limiter: &'a AdaptiveLimiter,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.limiter.release();
+            }
+        }
+    }
+}
+
+/// A slot in an [`AdaptiveLimiter`], released on drop. Report the outcome
+/// of the request it guarded with `success` or `overload`.
+pub struct LimiterPermit {
+    limiter: Arc<AdaptiveLimiter>,
+    generation: u64,
+}
+
+impl LimiterPermit {
+    pub fn success(&self) {
+        self.limiter.on_success();
+    }
+
+    pub fn overload(&self) {
+        self.limiter.on_overload(self.generation);
+    }
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn limit_grows_by_one_per_window_up_to_max() {
+        let limiter = Arc::new(AdaptiveLimiter::new(2, 1, 4));
+        let permit = limiter.acquire(None, Uuid::new_v4()).await;
+
+        permit.success();
+        assert_eq!(limiter.limit(), 2);
+        permit.success();
+        assert_eq!(limiter.limit(), 3);
+
+        // The window is as long as the new limit
+        for _ in 0..2 {
+            permit.success();
+        }
+        assert_eq!(limiter.limit(), 3);
+        permit.success();
+        assert_eq!(limiter.limit(), 4);
+
+        for _ in 0..20 {
+            permit.success();
+        }
+        assert_eq!(limiter.limit(), 4);
+    }
+
+    #[tokio::test]
+    async fn overload_halves_the_limit_down_to_min() {
+        let limiter = Arc::new(AdaptiveLimiter::new(10, 3, 16));
+        for expected in [5, 3, 3] {
+            let permit = limiter.acquire(None, Uuid::new_v4()).await;
+            permit.overload();
+            assert_eq!(limiter.limit(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn overloads_from_an_old_window_back_off_once() {
+        let limiter = Arc::new(AdaptiveLimiter::new(8, 1, 16));
+        let first = limiter.acquire(None, Uuid::new_v4()).await;
+        let second = limiter.acquire(None, Uuid::new_v4()).await;
+
+        first.overload();
+        assert_eq!(limiter.limit(), 4);
+        // Sent before the cut, so it doesn't count again
+        second.overload();
+        assert_eq!(limiter.limit(), 4);
+
+        let later = limiter.acquire(None, Uuid::new_v4()).await;
+        later.overload();
+        assert_eq!(limiter.limit(), 2);
+    }
+
+    #[test]
+    fn fair_queue_alternates_users_then_jobs() {
+        let (job_a, job_b, job_c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut queue = FairQueue::default();
+        let mut receivers = Vec::new();
+        for (label, user_id, job_id) in [
+            ("a1", Some(1), job_a),
+            ("a2", Some(1), job_a),
+            ("a3", Some(1), job_a),
+            ("b1", Some(1), job_b),
+            ("c1", Some(2), job_c),
+            ("c2", Some(2), job_c),
+        ] {
+            let (tx, rx) = oneshot::channel();
+            queue.push(user_id, job_id, tx);
+            receivers.push((label, rx));
+        }
+
+        for turn in 0..receivers.len() as u64 {
+            queue.pop().unwrap().send(turn).unwrap();
+        }
+        assert!(queue.is_empty());
+
+        let mut order: Vec<(u64, &str)> = receivers
+            .iter_mut()
+            .map(|(label, rx)| (rx.try_recv().unwrap(), *label))
+            .collect();
+        order.sort();
+        let order: Vec<&str> = order.into_iter().map(|(_, label)| label).collect();
+        assert_eq!(order, ["a1", "c1", "b1", "c2", "a2", "a3"]);
+    }
+
+    #[tokio::test]
+    async fn slot_sent_to_a_dropped_waiter_goes_to_the_next() {
+        let limiter = Arc::new(AdaptiveLimiter::new(1, 1, 1));
+        let held = limiter.acquire(Some(1), Uuid::new_v4()).await;
+
+        let gives_up = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Some(1), Uuid::new_v4()).await }
+        });
+        let waits = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Some(2), Uuid::new_v4()).await }
+        });
+        while limiter.state.lock().unwrap().waiting.users.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // The slot goes to the first waiter, which is gone before it sees it
+        drop(held);
+        gives_up.abort();
+        assert!(matches!(gives_up.await, Err(e) if e.is_cancelled()));
+
+        let permit = tokio::time::timeout(Duration::from_secs(1), waits)
+            .await
+            .expect("the released slot should reach the next waiter")
+            .unwrap();
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 1);
+        drop(permit);
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn waiter_dropped_before_its_turn_takes_no_slot() {
+        let limiter = Arc::new(AdaptiveLimiter::new(1, 1, 1));
+        let held = limiter.acquire(Some(1), Uuid::new_v4()).await;
+
+        let gives_up = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Some(2), Uuid::new_v4()).await }
+        });
+        while limiter.state.lock().unwrap().waiting.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        gives_up.abort();
+        assert!(matches!(gives_up.await, Err(e) if e.is_cancelled()));
+
+        drop(held);
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 0);
+        assert!(limiter.state.lock().unwrap().waiting.is_empty());
+    }
+}
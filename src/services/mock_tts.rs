@@ -0,0 +1,218 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+use crate::services::tts_service::{SynthesisRequest, TtsError, TtsProvider, TTS_FORMAT};
+
+/// The wait injected 429s ask for with `Retry-After`.
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Characters read per second at speed 1, about 150 words a minute.
+const CHARS_PER_SECOND: f32 = 15.0;
+
+/// Header of an MPEG-1 Layer III frame: 32 kbit/s, 44.1 kHz, mono, no CRC.
+const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x10, 0xC0];
+/// Bytes per frame at that bitrate and sample rate: 144 * 32000 / 44100.
+const FRAME_BYTES: usize = 104;
+/// Each frame holds 1152 samples.
+const FRAME_SECONDS: f32 = 1152.0 / 44100.0;
+
+/// A provider that needs no network: every chunk comes back as valid MP3
+/// silence, as long as the text would take to read out at the requested
+/// speed. The same request always yields the same audio, so jobs merge and
+/// cache exactly like real ones.
+///
+/// Latency and errors can be injected to exercise the limiter, retries and
+/// failed jobs: each request fails with a 500 with probability
+/// `failure_rate` and with a 429 with probability `rate_limit_rate`. The
+/// 429s carry a one second `Retry-After`, like a real provider's would.
+pub struct MockTts {
+    latency: Duration,
+    failure_rate: f64,
+    rate_limit_rate: f64,
+}
+
+impl MockTts {
+    pub fn new(latency: Duration, failure_rate: f64, rate_limit_rate: f64) -> Self {
+        Self {
+            latency,
+            failure_rate,
+            rate_limit_rate,
+        }
+    }
+}
+
+#[axum::async_trait]
+impl TtsProvider for MockTts {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn synthesize(&self, request: &SynthesisRequest<'_>) -> Result<Vec<u8>, TtsError> {
+        tokio::time::sleep(self.latency).await;
+
+        let roll: f64 = rand::thread_rng().gen();
+        if roll < self.failure_rate {
+            return Err(TtsError::new(
+                Some(StatusCode::INTERNAL_SERVER_ERROR),
+                "TTS request failed: 500 Internal Server Error - injected by the mock provider"
+                    .to_string(),
+            ));
+        }
+        if roll < self.failure_rate + self.rate_limit_rate {
+            let mut error = TtsError::new(
+                Some(StatusCode::TOO_MANY_REQUESTS),
+                "TTS request failed: 429 Too Many Requests - injected by the mock provider"
+                    .to_string(),
+            );
+            error.retry_after = Some(RETRY_AFTER);
+            return Err(error);
+        }
+
+        let seconds = request.text.chars().count() as f32 / (CHARS_PER_SECOND * request.speed);
+        let frames = (seconds / FRAME_SECONDS).ceil().max(1.0) as usize;
+        Ok(silence(frames))
+    }
+
+    fn voices(&self) -> &[&str] {
+        &[
+            "alloy", "ash", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer",
+        ]
+    }
+
+    fn default_voice(&self) -> &str {
+        "onyx"
+    }
+
+    fn models(&self) -> &[&str] {
+        &["tts-1", "tts-1-hd"]
+    }
+
+    fn default_model(&self) -> &str {
+        "tts-1"
+    }
+
+    fn formats(&self) -> &[&str] {
+        &[TTS_FORMAT]
+    }
+
+    fn speed_range(&self) -> (f32, f32) {
+        (0.25, 4.0)
+    }
+
+    fn max_input_chars(&self) -> usize {
+        4096
+    }
+}
+
+/// `frames` silent MP3 frames. With the side information all zero, a frame
+/// carries no Huffman data and decodes to zero samples.
+fn silence(frames: usize) -> Vec<u8> {
+    let mut frame = [0u8; FRAME_BYTES];
+    frame[..FRAME_HEADER.len()].copy_from_slice(&FRAME_HEADER);
+    frame.repeat(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage::{AudioStorage, LocalStorage};
+    use crate::utils::concat_mp3::concat_mp3;
+
+    fn mock() -> MockTts {
+        MockTts::new(Duration::ZERO, 0.0, 0.0)
+    }
+
+    fn request(text: &str, speed: f32) -> SynthesisRequest<'_> {
+        SynthesisRequest {
+            text,
+            voice: "onyx",
+            model: "tts-1",
+            format: TTS_FORMAT,
+            speed,
+        }
+    }
+
+    async fn frames(text: &str, speed: f32) -> usize {
+        mock()
+            .synthesize(&request(text, speed))
+            .await
+            .unwrap()
+            .len()
+            / FRAME_BYTES
+    }
+
+    #[tokio::test]
+    async fn audio_is_whole_mp3_frames() {
+        let audio = mock()
+            .synthesize(&request("Hello there, general reader.", 1.0))
+            .await
+            .unwrap();
+
+        assert!(!audio.is_empty());
+        assert_eq!(audio.len() % FRAME_BYTES, 0);
+        for frame in audio.chunks(FRAME_BYTES) {
+            assert_eq!(frame[..4], FRAME_HEADER);
+        }
+    }
+
+    #[tokio::test]
+    async fn length_follows_the_text_and_the_speed() {
+        let short = "a".repeat(100);
+        let long = "a".repeat(1_000);
+        assert!(frames(&long, 1.0).await > frames(&short, 1.0).await);
+        assert!(frames(&long, 2.0).await < frames(&long, 1.0).await);
+        assert!(frames(&long, 4.0).await < frames(&long, 2.0).await);
+        // Even the shortest text gets a frame
+        assert_eq!(frames("a", 4.0).await, 1);
+    }
+
+    #[tokio::test]
+    async fn the_same_request_gives_the_same_audio() {
+        let request = request("The same words, twice.", 1.25);
+        let first = mock().synthesize(&request).await.unwrap();
+        let second = mock().synthesize(&request).await.unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn injected_failures() {
+        let failing = MockTts::new(Duration::ZERO, 1.0, 0.0);
+        let error = failing.synthesize(&request("text", 1.0)).await.unwrap_err();
+        assert_eq!(error.status, Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(error.retryable);
+
+        let limited = MockTts::new(Duration::ZERO, 0.0, 1.0);
+        let error = limited.synthesize(&request("text", 1.0)).await.unwrap_err();
+        assert_eq!(error.status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(error.retry_after, Some(RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn chunks_merge_into_one_stream_of_frames() {
+        let root = std::env::temp_dir().join(format!("mock-tts-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(root.clone());
+
+        let texts = ["First chunk.", "A somewhat longer second chunk.", "Last."];
+        let mut keys = Vec::new();
+        let mut expected = 0;
+        for (i, text) in texts.iter().enumerate() {
+            let audio = mock().synthesize(&request(text, 1.0)).await.unwrap();
+            expected += audio.len();
+            let key = format!("job/speech-chunk-{}.mp3", i + 1);
+            storage.put(&key, audio).await.unwrap();
+            keys.push(key);
+        }
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+        let size = concat_mp3(&storage, &keys, "job/speech.mp3").await.unwrap();
+        let merged = storage.get("job/speech.mp3").await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(size as usize, expected);
+        assert_eq!(merged.len(), expected);
+        for frame in merged.chunks(FRAME_BYTES) {
+            assert_eq!(frame[..4], FRAME_HEADER);
+        }
+    }
+}
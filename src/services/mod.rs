@@ -1 +1,15 @@
+pub mod adaptive_limiter;
+pub mod mock_tts;
+pub mod plans;
+pub mod speech_batches;
+pub mod speech_cache;
+pub mod speech_error;
+pub mod speech_events;
+pub mod speech_gc;
+pub mod speech_jobs;
+pub mod speech_pipeline;
+pub mod speech_stream;
+pub mod storage;
 pub mod tts_service;
+pub mod usage;
+pub mod webhooks;
@@ -0,0 +1,36 @@
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Plan applied to requests that are not tied to a user.
+pub const DEFAULT_PLAN: &str = "free";
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Plan {
+    pub name: String,
+    /// How many chunks of a single job may be synthesized at once.
+    pub max_concurrency: i32,
+    /// How long finished jobs keep their audio, unless the user overrides it.
+    pub retention_days: i32,
+    /// Characters that may be sent to the TTS provider per calendar month,
+    /// unless the user overrides it.
+    pub monthly_chars: i64,
+}
+
+pub async fn plan_for_user(db: &PgPool, user_id: Option<i32>) -> Result<Plan, sqlx::Error> {
+    match user_id {
+        Some(user_id) => {
+            sqlx::query_as(
+                "SELECT p.* FROM plans p JOIN users u ON u.plan = p.name WHERE u.id = $1",
+            )
+            .bind(user_id)
+            .fetch_one(db)
+            .await
+        }
+        None => {
+            sqlx::query_as("SELECT * FROM plans WHERE name = $1")
+                .bind(DEFAULT_PLAN)
+                .fetch_one(db)
+                .await
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::services::speech_jobs::{self, JobState, NewSpeechJob, SpeechJob};
+
+/// A batch of documents and the progress of their jobs, as reported by
+/// `GET /api/speech/batches/:id`.
+#[derive(Debug, Serialize)]
+pub struct SpeechBatch {
+    pub id: Uuid,
+    /// Queued until any job starts, running while any job is unfinished,
+    /// then done if every job is, otherwise failed or cancelled.
+    pub state: JobState,
+    pub jobs_total: usize,
+    pub jobs_done: usize,
+    pub jobs_failed: usize,
+    pub jobs_cancelled: usize,
+    pub chunks_done: i64,
+    pub chunks_total: i64,
+    pub created_at: DateTime<Utc>,
+    /// The batch's jobs in the order their documents were submitted.
+    pub jobs: Vec<SpeechJob>,
+}
+
+/// Where each document of a batch ended up.
+#[derive(Debug, Serialize)]
+pub struct BatchManifest {
+    pub batch_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// Whether every document has merged audio.
+    pub complete: bool,
+    pub documents: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// The document's position in the batch, from 1.
+    pub position: usize,
+    pub title: Option<String>,
+    pub job_id: Uuid,
+    pub state: JobState,
+    pub voice: String,
+    pub model: String,
+    pub speed: f32,
+    pub input_chars: i32,
+    /// Storage key of the merged audio, once there is any.
+    pub merged_file: Option<String>,
+    pub merged_bytes: Option<i64>,
+    pub audio_url: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Records a batch and one queued job per document as part of `tx`, so a
+/// batch is never left half created. Returns the batch ID and the job IDs
+/// in document order.
+pub async fn create_batch(
+    tx: &mut PgConnection,
+    user_id: i32,
+    jobs: &[NewSpeechJob<'_>],
+) -> Result<(Uuid, Vec<Uuid>), sqlx::Error> {
+    let id = Uuid::new_v4();
+
+    sqlx::query("INSERT INTO speech_batches (id, user_id) VALUES ($1, $2)")
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut job_ids = Vec::with_capacity(jobs.len());
+    for (i, job) in jobs.iter().enumerate() {
+        let batch_idx = i as i32 + 1;
+        job_ids.push(speech_jobs::insert_job(&mut *tx, job, Some((id, batch_idx))).await?);
+    }
+
+    Ok((id, job_ids))
+}
+
+/// A batch owned by `user_id` with its jobs. Other users' batches look
+/// exactly like missing ones.
+pub async fn fetch_batch_for_user(
+    db: &PgPool,
+    id: Uuid,
+    user_id: i32,
+) -> Result<Option<SpeechBatch>, sqlx::Error> {
+    let created_at: Option<DateTime<Utc>> =
+        sqlx::query_scalar("SELECT created_at FROM speech_batches WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?;
+    let Some(created_at) = created_at else {
+        return Ok(None);
+    };
+
+    let mut jobs: Vec<SpeechJob> = sqlx::query_as(
+        r#"SELECT j.*,
+            (SELECT COUNT(*) FROM speech_chunks c WHERE c.job_id = j.id AND c.status = 'done') AS chunks_done,
+            (SELECT COUNT(*) FROM speech_chunks c WHERE c.job_id = j.id AND c.status = 'done' AND c.cached) AS cache_hits
+        FROM speech_jobs j
+        WHERE j.batch_id = $1
+        ORDER BY j.batch_idx"#,
+    )
+    .bind(id)
+    .fetch_all(db)
+    .await?;
+
+    for job in jobs.iter_mut().filter(|j| j.merged_file.is_some()) {
+        job.audio_url = Some(format!("/api/speech/jobs/{}/audio", job.id));
+    }
+
+    let count = |state: JobState| jobs.iter().filter(|j| j.state == state).count();
+    Ok(Some(SpeechBatch {
+        id,
+        state: batch_state(&jobs),
+        jobs_total: jobs.len(),
+        jobs_done: count(JobState::Done),
+        jobs_failed: count(JobState::Failed),
+        jobs_cancelled: count(JobState::Cancelled),
+        chunks_done: jobs.iter().map(|j| j.chunks_done).sum(),
+        chunks_total: jobs.iter().map(|j| j.chunks_total as i64).sum(),
+        created_at,
+        jobs,
+    }))
+}
+
+fn batch_state(jobs: &[SpeechJob]) -> JobState {
+    let any = |state: JobState| jobs.iter().any(|j| j.state == state);
+    if jobs.iter().all(|j| j.state == JobState::Queued) {
+        JobState::Queued
+    } else if any(JobState::Queued) || any(JobState::Running) {
+        JobState::Running
+    } else if jobs.iter().all(|j| j.state == JobState::Done) {
+        JobState::Done
+    } else if any(JobState::Failed) {
+        JobState::Failed
+    } else {
+        JobState::Cancelled
+    }
+}
+
+impl SpeechBatch {
+    pub fn manifest(&self) -> BatchManifest {
+        let documents: Vec<ManifestEntry> = self
+            .jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| ManifestEntry {
+                position: i + 1,
+                title: job.title.clone(),
+                job_id: job.id,
+                state: job.state,
+                voice: job.voice.clone(),
+                model: job.model.clone(),
+                speed: job.speed,
+                input_chars: job.input_chars,
+                merged_file: job.merged_file.clone(),
+                merged_bytes: job.merged_bytes,
+                audio_url: job.audio_url.clone(),
+                error: job.error.clone(),
+            })
+            .collect();
+
+        BatchManifest {
+            batch_id: self.id,
+            created_at: self.created_at,
+            complete: documents.iter().all(|d| d.merged_file.is_some()),
+            documents,
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::services::storage::AudioStorage;
+
+/// Everything that changes the audio a chunk synthesizes to.
+pub struct CacheParams<'a> {
+    pub provider: &'a str,
+    pub text: &'a str,
+    pub voice: &'a str,
+    pub model: &'a str,
+    pub format: &'a str,
+    pub speed: f32,
+}
+
+impl CacheParams<'_> {
+    /// The cache key: a SHA-256 over the provider, the normalized text and
+    /// the settings.
+    /// Text is NFC-normalized and its whitespace collapsed, since neither
+    /// changes what is spoken.
+    pub fn hash(&self) -> String {
+        let text: String = self.text.nfc().collect();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let mut hasher = Sha256::new();
+        for part in [
+            self.provider,
+            text.as_str(),
+            self.voice,
+            self.model,
+            self.format,
+            &self.speed.to_string(),
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+fn object_key(hash: &str, format: &str) -> String {
+    format!("cache/{}/{hash}.{format}", &hash[..2])
+}
+
+/// Returns cached audio for `hash`, bumping its last use. Entries whose
+/// object has gone missing are dropped, and any error counts as a miss.
+pub async fn lookup(db: &PgPool, storage: &dyn AudioStorage, hash: &str) -> Option<Vec<u8>> {
+    let key: Option<String> = sqlx::query_scalar(
+        r#"UPDATE speech_chunk_cache
+        SET hits = hits + 1, last_used_at = CURRENT_TIMESTAMP
+        WHERE hash = $1
+        RETURNING object_key"#,
+    )
+    .bind(hash)
+    .fetch_optional(db)
+    .await
+    .unwrap_or_else(|e| {
+        println!("[Speech cache] Lookup of {hash} failed: {e}");
+        None
+    });
+
+    match storage.get(&key?).await {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            println!("[Speech cache] Dropping {hash}: {e}");
+            let _ = sqlx::query("DELETE FROM speech_chunk_cache WHERE hash = $1")
+                .bind(hash)
+                .execute(db)
+                .await;
+            None
+        }
+    }
+}
+
+/// Adds freshly synthesized audio to the cache. Failures are only logged,
+/// since the job keeps its own copy of every chunk either way.
+pub async fn insert(
+    db: &PgPool,
+    storage: &dyn AudioStorage,
+    hash: &str,
+    format: &str,
+    audio: &[u8],
+) {
+    let key = object_key(hash, format);
+    if let Err(e) = storage.put(&key, audio.to_vec()).await {
+        println!("[Speech cache] Failed to store {key}: {e}");
+        return;
+    }
+
+    let res = sqlx::query(
+        r#"INSERT INTO speech_chunk_cache (hash, object_key, bytes)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (hash) DO UPDATE
+        SET object_key = EXCLUDED.object_key, bytes = EXCLUDED.bytes,
+            last_used_at = CURRENT_TIMESTAMP"#,
+    )
+    .bind(hash)
+    .bind(&key)
+    .bind(audio.len() as i64)
+    .execute(db)
+    .await;
+    if let Err(e) = res {
+        println!("[Speech cache] Failed to record {hash}: {e}");
+    }
+}
+
+/// Drops the least recently used entries until the cache fits in
+/// `max_bytes`. Returns how many entries were evicted.
+pub async fn evict(
+    db: &PgPool,
+    storage: &dyn AudioStorage,
+    max_bytes: u64,
+) -> Result<usize, sqlx::Error> {
+    let keys: Vec<String> = sqlx::query_scalar(
+        r#"DELETE FROM speech_chunk_cache
+        WHERE hash IN (
+            SELECT hash FROM (
+                SELECT hash, SUM(bytes) OVER (ORDER BY last_used_at DESC, hash) AS total
+                FROM speech_chunk_cache
+            ) ranked
+            WHERE total > $1
+        )
+        RETURNING object_key"#,
+    )
+    .bind(max_bytes as i64)
+    .fetch_all(db)
+    .await?;
+
+    for key in &keys {
+        if let Err(e) = storage.delete(key).await {
+            println!("[Speech cache] Failed to delete evicted {key}: {e}");
+        }
+    }
+    Ok(keys.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(text: &str) -> CacheParams<'_> {
+        CacheParams {
+            provider: "openai",
+            text,
+            voice: "onyx",
+            model: "tts-1",
+            format: "mp3",
+            speed: 1.0,
+        }
+    }
+
+    #[test]
+    fn normalization_and_whitespace_do_not_change_the_hash() {
+        // "é" precomposed (NFC) and as "e" plus a combining accent (NFD)
+        assert_eq!(
+            params("Caf\u{e9} au lait").hash(),
+            params("Cafe\u{301} au lait").hash()
+        );
+        assert_eq!(
+            params("One  two\nthree").hash(),
+            params(" One two\t three ").hash()
+        );
+    }
+
+    #[test]
+    fn every_setting_changes_the_hash() {
+        let base = params("Hello there.").hash();
+        let changed = [
+            CacheParams {
+                text: "Hello here.",
+                ..params("")
+            },
+            CacheParams {
+                provider: "mock",
+                ..params("Hello there.")
+            },
+            CacheParams {
+                voice: "nova",
+                ..params("Hello there.")
+            },
+            CacheParams {
+                model: "tts-1-hd",
+                ..params("Hello there.")
+            },
+            CacheParams {
+                format: "opus",
+                ..params("Hello there.")
+            },
+            CacheParams {
+                speed: 1.25,
+                ..params("Hello there.")
+            },
+        ];
+        for params in changed {
+            assert_ne!(params.hash(), base);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{fmt, io};
+
+use crate::services::tts_service::TtsError;
+
+/// Everything that can go wrong serving a speech request or running a job.
+///
+/// Responses carry the HTTP status from [`SpeechError::status`] and a body
+/// of the form `{"code": "rate_limited", "error": "<message>"}`. The same
+/// code is recorded on failed jobs and chunks as `error_code`.
+#[derive(Debug)]
+pub enum SpeechError {
+    /// The TTS provider asked us to slow down.
+    RateLimited(String),
+    /// The TTS provider rejected our credentials, or none are configured.
+    Auth(String),
+    /// Any other failure talking to the TTS provider, overload included.
+    Upstream(String),
+    /// The request can't be served as given; one entry per offending field.
+    Validation(Vec<FieldError>),
+    /// The caller has used up their quota; `details` says by how much.
+    QuotaExceeded {
+        message: String,
+        details: Value,
+    },
+    NotFound(String),
+    /// The caller may not use the endpoint at all.
+    Forbidden(String),
+    /// The job isn't in a state that allows the request.
+    Conflict(String),
+    /// Reading or writing audio failed.
+    Storage(String),
+    /// The chunk audio couldn't be merged.
+    Merge(String),
+    /// Some chunks of a job failed. Reported with the code of the first.
+    ChunksFailed {
+        failed: usize,
+        total: usize,
+        first: Box<SpeechError>,
+    },
+    /// The job was cancelled while it ran.
+    Cancelled,
+    /// The server stopped while the job was queued or running.
+    Interrupted,
+    /// Bookkeeping failures, mostly the database.
+    Internal(String),
+}
+
+/// Why one field of a request was rejected.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    /// The field's name, or its path for nested ones, as in
+    /// `documents[2].voice`.
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl SpeechError {
+    /// The machine-readable code clients can match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SpeechError::RateLimited(_) => "rate_limited",
+            SpeechError::Auth(_) => "provider_auth",
+            SpeechError::Upstream(_) => "upstream_error",
+            SpeechError::Validation(_) => "invalid_request",
+            SpeechError::QuotaExceeded { .. } => "quota_exceeded",
+            SpeechError::NotFound(_) => "not_found",
+            SpeechError::Forbidden(_) => "forbidden",
+            SpeechError::Conflict(_) => "conflict",
+            SpeechError::Storage(_) => "storage_error",
+            SpeechError::Merge(_) => "merge_error",
+            SpeechError::ChunksFailed { first, .. } => first.code(),
+            SpeechError::Cancelled => "cancelled",
+            SpeechError::Interrupted => "interrupted",
+            SpeechError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            SpeechError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            // Our credentials, not the caller's, so it's on us
+            SpeechError::Auth(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SpeechError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            SpeechError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            SpeechError::QuotaExceeded { .. } => StatusCode::PAYMENT_REQUIRED,
+            SpeechError::NotFound(_) => StatusCode::NOT_FOUND,
+            SpeechError::Forbidden(_) => StatusCode::FORBIDDEN,
+            SpeechError::Conflict(_) | SpeechError::Cancelled => StatusCode::CONFLICT,
+            SpeechError::Storage(_) | SpeechError::Merge(_) | SpeechError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            SpeechError::Interrupted => StatusCode::SERVICE_UNAVAILABLE,
+            SpeechError::ChunksFailed { first, .. } => first.status(),
+        }
+    }
+}
+
+impl fmt::Display for SpeechError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpeechError::RateLimited(msg)
+            | SpeechError::Auth(msg)
+            | SpeechError::Upstream(msg)
+            | SpeechError::QuotaExceeded { message: msg, .. }
+            | SpeechError::NotFound(msg)
+            | SpeechError::Forbidden(msg)
+            | SpeechError::Conflict(msg)
+            | SpeechError::Storage(msg)
+            | SpeechError::Merge(msg)
+            | SpeechError::Internal(msg) => f.write_str(msg),
+            SpeechError::ChunksFailed {
+                failed,
+                total,
+                first,
+            } => write!(
+                f,
+                "{failed} of {total} chunk(s) failed; resume the job to retry them. First error: {first}"
+            ),
+            SpeechError::Validation(fields) => {
+                f.write_str("Invalid request: ")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("; ")?;
+                    }
+                    write!(f, "{}: {}", field.field, field.message)?;
+                }
+                Ok(())
+            }
+            SpeechError::Cancelled => f.write_str("Cancelled"),
+            SpeechError::Interrupted => {
+                f.write_str("The server restarted before the job finished; resume it to continue")
+            }
+        }
+    }
+}
+
+impl From<TtsError> for SpeechError {
+    fn from(e: TtsError) -> Self {
+        let msg = format!("TTS error: {e}");
+        match e.status.map(|s| s.as_u16()) {
+            Some(429) => SpeechError::RateLimited(msg),
+            Some(401 | 403) => SpeechError::Auth(msg),
+            _ => SpeechError::Upstream(msg),
+        }
+    }
+}
+
+/// A body that isn't JSON, or doesn't fit the request's shape, is reported
+/// like any other invalid field.
+impl From<JsonRejection> for SpeechError {
+    fn from(rejection: JsonRejection) -> Self {
+        SpeechError::Validation(vec![FieldError::new("body", rejection.body_text())])
+    }
+}
+
+impl From<sqlx::Error> for SpeechError {
+    fn from(e: sqlx::Error) -> Self {
+        SpeechError::Internal(format!("Database error: {e}"))
+    }
+}
+
+impl From<io::Error> for SpeechError {
+    fn from(e: io::Error) -> Self {
+        SpeechError::Storage(format!("Storage error: {e}"))
+    }
+}
+
+impl IntoResponse for SpeechError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let mut body = json!({ "code": self.code(), "error": self.to_string() });
+        match self {
+            SpeechError::QuotaExceeded { details, .. } => body["details"] = details,
+            SpeechError::Validation(fields) => body["fields"] = json!(fields),
+            _ => {}
+        }
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn json_body(response: Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_error_maps_to_a_status_and_a_code() {
+        let msg = || "message".to_string();
+        let cases = [
+            (SpeechError::RateLimited(msg()), 429, "rate_limited"),
+            (SpeechError::Auth(msg()), 500, "provider_auth"),
+            (SpeechError::Upstream(msg()), 502, "upstream_error"),
+            (
+                SpeechError::Validation(vec![FieldError::new("speed", "is too fast")]),
+                422,
+                "invalid_request",
+            ),
+            (
+                SpeechError::QuotaExceeded {
+                    message: msg(),
+                    details: json!({ "remaining": 0 }),
+                },
+                402,
+                "quota_exceeded",
+            ),
+            (SpeechError::NotFound(msg()), 404, "not_found"),
+            (SpeechError::Forbidden(msg()), 403, "forbidden"),
+            (SpeechError::Conflict(msg()), 409, "conflict"),
+            (SpeechError::Storage(msg()), 500, "storage_error"),
+            (SpeechError::Merge(msg()), 500, "merge_error"),
+            (
+                SpeechError::ChunksFailed {
+                    failed: 2,
+                    total: 5,
+                    first: Box::new(SpeechError::RateLimited(msg())),
+                },
+                429,
+                "rate_limited",
+            ),
+            (SpeechError::Cancelled, 409, "cancelled"),
+            (SpeechError::Interrupted, 503, "interrupted"),
+            (SpeechError::Internal(msg()), 500, "internal_error"),
+        ];
+
+        for (error, status, code) in cases {
+            let message = error.to_string();
+            let response = error.into_response();
+            assert_eq!(response.status().as_u16(), status, "{code}");
+
+            let body = json_body(response).await;
+            assert_eq!(body["code"], code);
+            assert_eq!(body["error"], message.as_str());
+
+            let mut keys: Vec<&str> = body
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(|k| k.as_str())
+                .collect();
+            keys.sort();
+            let expected: &[&str] = match code {
+                "invalid_request" => &["code", "error", "fields"],
+                "quota_exceeded" => &["code", "details", "error"],
+                _ => &["code", "error"],
+            };
+            assert_eq!(keys, expected, "{code}");
+        }
+    }
+
+    #[tokio::test]
+    async fn validation_and_quota_errors_carry_their_details() {
+        let error = SpeechError::Validation(vec![
+            FieldError::new("input", "must not be empty"),
+            FieldError::new("speed", "must be between 0.25 and 4"),
+        ]);
+        let body = json_body(error.into_response()).await;
+        assert_eq!(
+            body["fields"],
+            json!([
+                { "field": "input", "message": "must not be empty" },
+                { "field": "speed", "message": "must be between 0.25 and 4" },
+            ])
+        );
+        assert_eq!(
+            body["error"],
+            "Invalid request: input: must not be empty; speed: must be between 0.25 and 4"
+        );
+
+        let error = SpeechError::QuotaExceeded {
+            message: "Monthly quota exceeded".to_string(),
+            details: json!({ "limit": 100, "used": 90 }),
+        };
+        let body = json_body(error.into_response()).await;
+        assert_eq!(body["details"], json!({ "limit": 100, "used": 90 }));
+    }
+}
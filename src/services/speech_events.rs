@@ -0,0 +1,120 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::services::speech_jobs::JobState;
+
+/// Progress events emitted by the speech pipeline, streamed to clients over
+/// `GET /api/speech/jobs/:id/events`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum JobEvent {
+    ChunkStarted {
+        index: i32,
+    },
+    ChunkFinished {
+        index: i32,
+        file: String,
+        bytes: i64,
+        cached: bool,
+    },
+    ChunkFailed {
+        index: i32,
+        error: String,
+        error_code: String,
+    },
+    MergeStarted {
+        chunks: usize,
+    },
+    JobComplete {
+        state: JobState,
+        merged_file: Option<String>,
+        error: Option<String>,
+        error_code: Option<String>,
+    },
+}
+
+impl JobEvent {
+    /// The SSE `event:` name, matching the serialized `event` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEvent::ChunkStarted { .. } => "chunk-started",
+            JobEvent::ChunkFinished { .. } => "chunk-finished",
+            JobEvent::ChunkFailed { .. } => "chunk-failed",
+            JobEvent::MergeStarted { .. } => "merge-started",
+            JobEvent::JobComplete { .. } => "job-complete",
+        }
+    }
+}
+
+/// One broadcast channel and cancellation token per running job. Both only
+/// exist while the pipeline runs; once it closes the channel, subscribers
+/// see the end of the stream.
+#[derive(Clone, Default)]
+pub struct JobEvents {
+    jobs: Arc<Mutex<HashMap<Uuid, RunningJob>>>,
+}
+
+struct RunningJob {
+    events: broadcast::Sender<JobEvent>,
+    cancel: CancellationToken,
+}
+
+impl RunningJob {
+    fn new() -> Self {
+        Self {
+            events: broadcast::channel(256).0,
+            cancel: CancellationToken::new(),
+        }
+    }
+}
+
+impl JobEvents {
+    /// Returns the sender for a job, creating its channel if needed.
+    pub fn open(&self, job_id: Uuid) -> broadcast::Sender<JobEvent> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entry(job_id)
+            .or_insert_with(RunningJob::new)
+            .events
+            .clone()
+    }
+
+    pub fn subscribe(&self, job_id: Uuid) -> Option<broadcast::Receiver<JobEvent>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&job_id)
+            .map(|job| job.events.subscribe())
+    }
+
+    /// The token the job's pipeline watches to know it should stop.
+    pub fn cancel_token(&self, job_id: Uuid) -> CancellationToken {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entry(job_id)
+            .or_insert_with(RunningJob::new)
+            .cancel
+            .clone()
+    }
+
+    /// Asks a job's pipeline to stop. Returns `false` if none is running.
+    pub fn cancel(&self, job_id: Uuid) -> bool {
+        match self.jobs.lock().unwrap().get(&job_id) {
+            Some(job) => {
+                job.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn close(&self, job_id: Uuid) {
+        self.jobs.lock().unwrap().remove(&job_id);
+    }
+}
@@ -0,0 +1,262 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::services::plans::DEFAULT_PLAN;
+use crate::services::speech_jobs;
+use crate::services::storage::AudioStorage;
+use crate::state::AppState;
+
+/// Jobs looked at per sweep and phase; anything left over waits for the next.
+const BATCH: i64 = 500;
+
+/// What one sweep did, or with `dry_run` would have done.
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Finished jobs whose chunk audio is redundant next to the merged file.
+    pub chunk_purges: Vec<ChunkPurge>,
+    /// Jobs past their owner's retention period, all audio included.
+    pub expirations: Vec<Expiration>,
+    /// Jobs left alone because their merged audio didn't check out.
+    pub skipped: Vec<Skipped>,
+    pub bytes_freed: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkPurge {
+    pub job_id: Uuid,
+    pub objects: usize,
+    pub bytes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Expiration {
+    pub job_id: Uuid,
+    pub user_id: Option<i32>,
+    pub finished_at: DateTime<Utc>,
+    pub retention_days: i32,
+    pub objects: usize,
+    pub bytes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Skipped {
+    pub job_id: Uuid,
+    pub reason: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct PurgeCandidate {
+    id: Uuid,
+    merged_key: String,
+    merged_bytes: Option<i64>,
+    keys: Vec<String>,
+    bytes: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct ExpiryCandidate {
+    id: Uuid,
+    user_id: Option<i32>,
+    finished_at: DateTime<Utc>,
+    retention_days: i32,
+    merged_key: Option<String>,
+    keys: Vec<String>,
+    bytes: i64,
+}
+
+/// Runs [`sweep`] every `interval` for as long as the server is up.
+pub fn spawn_sweeper(state: AppState, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match sweep(&state, interval, false).await {
+                Ok(report) => println!(
+                    "[Speech GC] Purged chunks of {} job(s), expired {} job(s), skipped {}, freed {} bytes",
+                    report.chunk_purges.len(),
+                    report.expirations.len(),
+                    report.skipped.len(),
+                    report.bytes_freed
+                ),
+                Err(e) => println!("[Speech GC] Sweep failed: {e}"),
+            }
+        }
+    });
+}
+
+/// Deletes speech audio that is no longer needed:
+///
+/// 1. All audio of finished, failed or cancelled jobs older than the owner's
+///    retention (`users.retention_days`, else their plan's), marking them
+///    expired.
+/// 2. Chunk audio of jobs that finished more than `grace` ago, once the
+///    merged object is confirmed to exist with the recorded size. The grace
+///    period leaves time to fetch chunks of a job that just finished.
+///
+/// With `dry_run`, storage is only read and nothing is deleted or updated.
+pub async fn sweep(
+    state: &AppState,
+    grace: Duration,
+    dry_run: bool,
+) -> Result<GcReport, sqlx::Error> {
+    let db = &state.db;
+    let storage = state.storage.as_ref();
+    let mut report = GcReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    // Expire first, so a dry run doesn't also count an expiring job's
+    // chunks as purged
+    let mut expired = HashSet::new();
+    for job in expiry_candidates(db).await? {
+        let keys: Vec<String> = job.merged_key.into_iter().chain(job.keys).collect();
+        if !dry_run {
+            // The job stays locked while its audio is deleted; if that
+            // fails, rolling back leaves it for the next sweep
+            let mut tx = db.begin().await?;
+            if !speech_jobs::expire_job(&mut tx, job.id, job.finished_at).await? {
+                report.skipped.push(changed(job.id));
+                continue;
+            }
+            if let Err(e) = delete_all(storage, &keys).await {
+                println!("[Speech GC] Failed to delete audio of job {}: {e}", job.id);
+                continue;
+            }
+            tx.commit().await?;
+        }
+        expired.insert(job.id);
+        report.bytes_freed += job.bytes;
+        report.expirations.push(Expiration {
+            job_id: job.id,
+            user_id: job.user_id,
+            finished_at: job.finished_at,
+            retention_days: job.retention_days,
+            objects: keys.len(),
+            bytes: job.bytes,
+        });
+    }
+
+    let cutoff = Utc::now() - chrono::Duration::from_std(grace).unwrap_or_default();
+    for job in purge_candidates(db, cutoff).await? {
+        if expired.contains(&job.id) {
+            continue;
+        }
+        if let Err(reason) = verify_merged(storage, &job.merged_key, job.merged_bytes).await {
+            report.skipped.push(Skipped {
+                job_id: job.id,
+                reason,
+            });
+            continue;
+        }
+
+        if !dry_run {
+            let mut tx = db.begin().await?;
+            if !speech_jobs::clear_chunk_keys(&mut tx, job.id, &job.merged_key).await? {
+                report.skipped.push(changed(job.id));
+                continue;
+            }
+            if let Err(e) = delete_all(storage, &job.keys).await {
+                println!("[Speech GC] Failed to delete chunks of job {}: {e}", job.id);
+                continue;
+            }
+            tx.commit().await?;
+        }
+        report.bytes_freed += job.bytes;
+        report.chunk_purges.push(ChunkPurge {
+            job_id: job.id,
+            objects: job.keys.len(),
+            bytes: job.bytes,
+        });
+    }
+
+    Ok(report)
+}
+
+async fn purge_candidates(
+    db: &PgPool,
+    finished_before: DateTime<Utc>,
+) -> Result<Vec<PurgeCandidate>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT j.id, j.merged_key, j.merged_bytes,
+            array_agg(c.object_key) AS keys,
+            COALESCE(SUM(c.bytes), 0)::BIGINT AS bytes
+        FROM speech_jobs j
+        JOIN speech_chunks c ON c.job_id = j.id AND c.object_key IS NOT NULL
+        WHERE j.status = 'done' AND j.merged_key IS NOT NULL AND j.expired_at IS NULL
+            AND j.finished_at < $1
+        GROUP BY j.id
+        ORDER BY j.finished_at
+        LIMIT $2"#,
+    )
+    .bind(finished_before)
+    .bind(BATCH)
+    .fetch_all(db)
+    .await
+}
+
+async fn expiry_candidates(db: &PgPool) -> Result<Vec<ExpiryCandidate>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT j.id, j.user_id, j.finished_at, j.merged_key,
+            COALESCE(u.retention_days, p.retention_days) AS retention_days,
+            COALESCE(array_agg(c.object_key) FILTER (WHERE c.object_key IS NOT NULL), '{}') AS keys,
+            (COALESCE(j.merged_bytes, 0)
+                + COALESCE(SUM(c.bytes) FILTER (WHERE c.object_key IS NOT NULL), 0))::BIGINT AS bytes
+        FROM speech_jobs j
+        LEFT JOIN users u ON u.id = j.user_id
+        JOIN plans p ON p.name = COALESCE(u.plan, $1)
+        LEFT JOIN speech_chunks c ON c.job_id = j.id
+        WHERE j.status IN ('done', 'failed', 'cancelled') AND j.expired_at IS NULL
+            AND j.finished_at < CURRENT_TIMESTAMP
+                - make_interval(days => COALESCE(u.retention_days, p.retention_days))
+        GROUP BY j.id, u.retention_days, p.retention_days
+        ORDER BY j.finished_at
+        LIMIT $2"#,
+    )
+    .bind(DEFAULT_PLAN)
+    .bind(BATCH)
+    .fetch_all(db)
+    .await
+}
+
+/// A job that was started again between being picked and being cleaned
+/// up, and may be using its audio again.
+fn changed(job_id: Uuid) -> Skipped {
+    Skipped {
+        job_id,
+        reason: "Job changed since the sweep started".to_string(),
+    }
+}
+
+/// The merged object must exist with the size recorded when it was written
+/// before the chunks it was built from are thrown away.
+async fn verify_merged(
+    storage: &dyn AudioStorage,
+    key: &str,
+    expected: Option<i64>,
+) -> Result<(), String> {
+    let info = storage
+        .head(key)
+        .await
+        .map_err(|e| format!("Merged audio {key} unavailable: {e}"))?;
+    match expected {
+        Some(expected) if info.size as i64 == expected => Ok(()),
+        Some(expected) => Err(format!(
+            "Merged audio {key} is {} bytes, expected {expected}",
+            info.size
+        )),
+        None => Err(format!("Merged audio {key} has no recorded size")),
+    }
+}
+
+async fn delete_all(storage: &dyn AudioStorage, keys: &[String]) -> std::io::Result<()> {
+    for key in keys {
+        storage.delete(key).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,821 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgRow;
+use sqlx::{PgConnection, PgExecutor, PgPool, Row};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::services::speech_error::SpeechError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, sqlx::Error> {
+        match s {
+            "queued" => Ok(JobState::Queued),
+            "running" => Ok(JobState::Running),
+            "done" => Ok(JobState::Done),
+            "failed" => Ok(JobState::Failed),
+            "cancelled" => Ok(JobState::Cancelled),
+            other => Err(sqlx::Error::Decode(
+                format!("unknown speech job status {other:?}").into(),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+impl ChunkState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChunkState::Pending => "pending",
+            ChunkState::Running => "running",
+            ChunkState::Done => "done",
+            ChunkState::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, sqlx::Error> {
+        match s {
+            "pending" => Ok(ChunkState::Pending),
+            "running" => Ok(ChunkState::Running),
+            "done" => Ok(ChunkState::Done),
+            "failed" => Ok(ChunkState::Failed),
+            other => Err(sqlx::Error::Decode(
+                format!("unknown speech chunk status {other:?}").into(),
+            )),
+        }
+    }
+}
+
+/// A row of `speech_jobs`, as reported by `GET /api/speech/jobs/:id`.
+#[derive(Debug, Serialize)]
+pub struct SpeechJob {
+    pub id: Uuid,
+    pub user_id: Option<i32>,
+    pub state: JobState,
+    pub input_hash: String,
+    pub input_chars: i32,
+    pub voice: String,
+    pub model: String,
+    pub speed: f32,
+    /// The batch the job was submitted in, if any.
+    pub batch_id: Option<Uuid>,
+    /// Title of the job's document, as given in its batch.
+    pub title: Option<String>,
+    /// Starts at 1 and goes up each time the job's input is resubmitted.
+    pub revision: i32,
+    pub chunks_done: i64,
+    /// Finished chunks whose audio came from the chunk cache.
+    pub cache_hits: i64,
+    pub chunks_total: i32,
+    /// Storage key prefix the job's audio is written under.
+    pub output_prefix: Option<String>,
+    /// Storage key of the merged audio.
+    pub merged_file: Option<String>,
+    pub merged_bytes: Option<i64>,
+    /// Where the merged audio can be downloaded, once there is any.
+    pub audio_url: Option<String>,
+    pub error: Option<String>,
+    /// Machine-readable code of `error`, as in `SpeechError::code`.
+    pub error_code: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// When the job's audio was deleted for being past retention.
+    pub expired_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<SpeechChunk>,
+}
+
+impl<'r> sqlx::FromRow<'r, PgRow> for SpeechJob {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        let status: String = row.try_get("status")?;
+
+        Ok(Self {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            state: JobState::parse(&status)?,
+            input_hash: row.try_get("input_hash")?,
+            input_chars: row.try_get("input_chars")?,
+            voice: row.try_get("voice")?,
+            model: row.try_get("model")?,
+            speed: row.try_get("speed")?,
+            batch_id: row.try_get("batch_id")?,
+            title: row.try_get("title")?,
+            revision: row.try_get("revision")?,
+            chunks_done: row.try_get("chunks_done")?,
+            cache_hits: row.try_get("cache_hits")?,
+            chunks_total: row.try_get("chunks_total")?,
+            output_prefix: row.try_get("output_prefix")?,
+            merged_file: row.try_get("merged_key")?,
+            merged_bytes: row.try_get("merged_bytes")?,
+            audio_url: None,
+            error: row.try_get("error")?,
+            error_code: row.try_get("error_code")?,
+            created_at: row.try_get("created_at")?,
+            finished_at: row.try_get("finished_at")?,
+            expired_at: row.try_get("expired_at")?,
+            chunks: Vec::new(),
+        })
+    }
+}
+
+/// A row of `speech_chunks`. `index` is 1-based, matching the chunk file names.
+#[derive(Debug, Serialize)]
+pub struct SpeechChunk {
+    pub index: i32,
+    pub state: ChunkState,
+    pub input_chars: i32,
+    pub file: Option<String>,
+    pub bytes: Option<i64>,
+    /// Whether the audio came from the chunk cache instead of the provider.
+    pub cached: bool,
+    /// How many times the TTS request was retried in the chunk's latest
+    /// run.
+    pub retries: i32,
+    pub audio_url: Option<String>,
+    pub error: Option<String>,
+    pub error_code: Option<String>,
+}
+
+impl<'r> sqlx::FromRow<'r, PgRow> for SpeechChunk {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        let status: String = row.try_get("status")?;
+
+        Ok(Self {
+            index: row.try_get("idx")?,
+            state: ChunkState::parse(&status)?,
+            input_chars: row.try_get("input_chars")?,
+            file: row.try_get("object_key")?,
+            bytes: row.try_get("bytes")?,
+            cached: row.try_get("cached")?,
+            retries: row.try_get("retries")?,
+            audio_url: None,
+            error: row.try_get("error")?,
+            error_code: row.try_get("error_code")?,
+        })
+    }
+}
+
+/// Everything needed to record a job before its pipeline starts.
+pub struct NewSpeechJob<'a> {
+    pub user_id: Option<i32>,
+    pub input_hash: &'a str,
+    pub input_chars: i32,
+    pub voice: &'a str,
+    pub model: &'a str,
+    pub speed: f32,
+    pub title: Option<&'a str>,
+    /// The `Idempotency-Key` the job was submitted with, and the hash of
+    /// the request it came with.
+    pub idempotency: Option<(&'a str, &'a str)>,
+    /// The chunked input, in order.
+    pub chunks: &'a [String],
+}
+
+/// Inserts a queued job together with one pending row per chunk as part of
+/// `tx`. `batch` is the batch the job belongs to and its position there.
+pub async fn insert_job(
+    tx: &mut PgConnection,
+    job: &NewSpeechJob<'_>,
+    batch: Option<(Uuid, i32)>,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+
+    sqlx::query(
+        r#"INSERT INTO speech_jobs
+        (id, user_id, input_hash, input_chars, voice, model, speed, title, batch_id, batch_idx,
+            idempotency_key, request_hash, chunks_total)
+        VALUES
+        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"#,
+    )
+    .bind(id)
+    .bind(job.user_id)
+    .bind(job.input_hash)
+    .bind(job.input_chars)
+    .bind(job.voice)
+    .bind(job.model)
+    .bind(job.speed)
+    .bind(job.title)
+    .bind(batch.map(|(batch_id, _)| batch_id))
+    .bind(batch.map(|(_, idx)| idx))
+    .bind(job.idempotency.map(|(key, _)| key))
+    .bind(job.idempotency.map(|(_, hash)| hash))
+    .bind(job.chunks.len() as i32)
+    .execute(&mut *tx)
+    .await?;
+
+    let indexes: Vec<i32> = (1..=job.chunks.len() as i32).collect();
+    let chunk_chars: Vec<i32> = job
+        .chunks
+        .iter()
+        .map(|c| c.chars().count() as i32)
+        .collect();
+    sqlx::query(
+        r#"INSERT INTO speech_chunks (job_id, idx, input_chars, text)
+        SELECT $1, * FROM UNNEST($2::INT[], $3::INT[], $4::TEXT[])"#,
+    )
+    .bind(id)
+    .bind(&indexes)
+    .bind(&chunk_chars)
+    .bind(job.chunks)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(id)
+}
+
+/// The job `user_id` submitted with `idempotency_key`, and the hash of the
+/// request it was submitted with.
+pub async fn find_idempotent_job(
+    db: &PgPool,
+    user_id: i32,
+    idempotency_key: &str,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, request_hash FROM speech_jobs WHERE user_id = $1 AND idempotency_key = $2",
+    )
+    .bind(user_id)
+    .bind(idempotency_key)
+    .fetch_optional(db)
+    .await
+}
+
+/// What [`revise_job`] did to a job's chunks.
+#[derive(Debug)]
+pub struct Revision {
+    pub revision: i32,
+    /// New chunks whose audio was carried over from the previous revision.
+    pub chunks_reused: usize,
+    /// New chunks that have to be synthesized.
+    pub chunks_pending: usize,
+    /// Storage keys the new revision no longer uses: the merged audio and
+    /// any chunk audio that wasn't carried over.
+    pub stale_keys: Vec<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct PreviousChunk {
+    text: Option<String>,
+    status: String,
+    object_key: Option<String>,
+    bytes: Option<i64>,
+}
+
+/// Replaces the input of a finished, failed or cancelled job with `chunks`
+/// and queues it again. Each new chunk whose text matches a finished chunk
+/// of the previous revision takes over that chunk's audio, wherever in the
+/// document it moved to; the rest start out pending. Returns `None` if the
+/// job does not exist or is still queued or running. Everything happens as
+/// part of `tx`.
+pub async fn revise_job(
+    tx: &mut PgConnection,
+    id: Uuid,
+    input_hash: &str,
+    input_chars: i32,
+    chunks: &[String],
+) -> Result<Option<Revision>, sqlx::Error> {
+    let current: Option<(i32, Option<String>)> = sqlx::query_as(
+        r#"SELECT revision, merged_key FROM speech_jobs
+        WHERE id = $1 AND status IN ($2, $3, $4)
+        FOR UPDATE"#,
+    )
+    .bind(id)
+    .bind(JobState::Done.as_str())
+    .bind(JobState::Failed.as_str())
+    .bind(JobState::Cancelled.as_str())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((revision, merged_key)) = current else {
+        return Ok(None);
+    };
+
+    let previous: Vec<PreviousChunk> = sqlx::query_as(
+        "SELECT text, status, object_key, bytes FROM speech_chunks WHERE job_id = $1 ORDER BY idx",
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // Finished audio by chunk text; identical chunks can share one object
+    let mut reusable: HashMap<&str, (&str, Option<i64>)> = HashMap::new();
+    for chunk in &previous {
+        if chunk.status != ChunkState::Done.as_str() {
+            continue;
+        }
+        if let (Some(text), Some(key)) = (&chunk.text, &chunk.object_key) {
+            reusable.entry(text).or_insert((key, chunk.bytes));
+        }
+    }
+
+    let mut statuses = Vec::with_capacity(chunks.len());
+    let mut keys = Vec::with_capacity(chunks.len());
+    let mut bytes = Vec::with_capacity(chunks.len());
+    for text in chunks {
+        let reused = reusable.get(text.as_str());
+        let state = match reused {
+            Some(_) => ChunkState::Done,
+            None => ChunkState::Pending,
+        };
+        statuses.push(state.as_str());
+        keys.push(reused.map(|(key, _)| key.to_string()));
+        bytes.push(reused.and_then(|(_, bytes)| *bytes));
+    }
+
+    let kept: HashSet<&str> = keys.iter().flatten().map(|k| k.as_str()).collect();
+    // Identical chunks of an earlier revision may share a key
+    let mut stale_keys: Vec<String> = previous
+        .iter()
+        .filter_map(|c| c.object_key.as_deref())
+        .filter(|key| !kept.contains(key))
+        .chain(merged_key.as_deref())
+        .map(str::to_string)
+        .collect();
+    stale_keys.sort();
+    stale_keys.dedup();
+    let chunks_reused = keys.iter().filter(|k| k.is_some()).count();
+
+    sqlx::query("DELETE FROM speech_chunks WHERE job_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    let indexes: Vec<i32> = (1..=chunks.len() as i32).collect();
+    let chunk_chars: Vec<i32> = chunks.iter().map(|c| c.chars().count() as i32).collect();
+    sqlx::query(
+        r#"INSERT INTO speech_chunks (job_id, idx, input_chars, text, status, object_key, bytes)
+        SELECT $1, * FROM UNNEST($2::INT[], $3::INT[], $4::TEXT[], $5::VARCHAR[], $6::VARCHAR[], $7::BIGINT[])"#,
+    )
+    .bind(id)
+    .bind(&indexes)
+    .bind(&chunk_chars)
+    .bind(chunks)
+    .bind(&statuses)
+    .bind(&keys)
+    .bind(&bytes)
+    .execute(&mut *tx)
+    .await?;
+
+    let revision = revision + 1;
+    sqlx::query(
+        r#"UPDATE speech_jobs
+        SET revision = $1, input_hash = $2, input_chars = $3, chunks_total = $4,
+            status = $5, error = NULL, error_code = NULL, merged_key = NULL,
+            merged_bytes = NULL, updated_at = CURRENT_TIMESTAMP, finished_at = NULL,
+            expired_at = NULL
+        WHERE id = $6"#,
+    )
+    .bind(revision)
+    .bind(input_hash)
+    .bind(input_chars)
+    .bind(chunks.len() as i32)
+    .bind(JobState::Queued.as_str())
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(Some(Revision {
+        revision,
+        chunks_reused,
+        chunks_pending: chunks.len() - chunks_reused,
+        stale_keys,
+    }))
+}
+
+pub async fn fetch_job(db: &PgPool, id: Uuid) -> Result<Option<SpeechJob>, sqlx::Error> {
+    let job: Option<SpeechJob> = sqlx::query_as(
+        r#"SELECT j.*,
+            (SELECT COUNT(*) FROM speech_chunks c WHERE c.job_id = j.id AND c.status = 'done') AS chunks_done,
+            (SELECT COUNT(*) FROM speech_chunks c WHERE c.job_id = j.id AND c.status = 'done' AND c.cached) AS cache_hits
+        FROM speech_jobs j
+        WHERE j.id = $1"#,
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+
+    let Some(mut job) = job else {
+        return Ok(None);
+    };
+
+    job.chunks = sqlx::query_as(
+        r#"SELECT idx, status, input_chars, object_key, bytes, cached, retries, error, error_code
+        FROM speech_chunks
+        WHERE job_id = $1
+        ORDER BY idx"#,
+    )
+    .bind(id)
+    .fetch_all(db)
+    .await?;
+
+    if job.merged_file.is_some() {
+        job.audio_url = Some(format!("/api/speech/jobs/{id}/audio"));
+    }
+    for chunk in job.chunks.iter_mut().filter(|c| c.file.is_some()) {
+        chunk.audio_url = Some(format!(
+            "/api/speech/jobs/{id}/chunks/{}/audio",
+            chunk.index
+        ));
+    }
+
+    Ok(Some(job))
+}
+
+/// Like [`fetch_job`], but only finds jobs owned by `user_id`. Other users'
+/// jobs look exactly like missing ones, so their IDs aren't confirmed.
+pub async fn fetch_job_for_user(
+    db: &PgPool,
+    id: Uuid,
+    user_id: i32,
+) -> Result<Option<SpeechJob>, sqlx::Error> {
+    let job = fetch_job(db, id).await?;
+    Ok(job.filter(|job| job.user_id == Some(user_id)))
+}
+
+/// A user's most recent jobs, newest first, without their chunks.
+pub async fn list_user_jobs(db: &PgPool, user_id: i32) -> Result<Vec<SpeechJob>, sqlx::Error> {
+    let mut jobs: Vec<SpeechJob> = sqlx::query_as(
+        r#"SELECT j.*,
+            (SELECT COUNT(*) FROM speech_chunks c WHERE c.job_id = j.id AND c.status = 'done') AS chunks_done,
+            (SELECT COUNT(*) FROM speech_chunks c WHERE c.job_id = j.id AND c.status = 'done' AND c.cached) AS cache_hits
+        FROM speech_jobs j
+        WHERE j.user_id = $1
+        ORDER BY j.created_at DESC
+        LIMIT 100"#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    for job in jobs.iter_mut().filter(|j| j.merged_file.is_some()) {
+        job.audio_url = Some(format!("/api/speech/jobs/{}/audio", job.id));
+    }
+    Ok(jobs)
+}
+
+/// The parts of a chunk row the pipeline needs to (re-)synthesize it.
+#[derive(sqlx::FromRow)]
+pub struct ChunkWork {
+    pub idx: i32,
+    pub status: String,
+    pub text: Option<String>,
+    pub object_key: Option<String>,
+}
+
+impl ChunkWork {
+    /// The chunk's storage key, if it finished. Whether the object is still
+    /// in storage is up to the caller to check.
+    pub fn done_key(&self) -> Option<&str> {
+        let done = self.status == ChunkState::Done.as_str();
+        self.object_key.as_deref().filter(|_| done)
+    }
+}
+
+pub async fn fetch_chunk_work(db: &PgPool, job_id: Uuid) -> Result<Vec<ChunkWork>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT idx, status, text, object_key FROM speech_chunks WHERE job_id = $1 ORDER BY idx",
+    )
+    .bind(job_id)
+    .fetch_all(db)
+    .await
+}
+
+/// Fails every job left `queued` or `running` by a previous run of the
+/// server, with code `interrupted`, so that it can be resumed and stops
+/// holding on to its owner's quota. Only call this on startup, before any
+/// job is spawned. Returns how many jobs were failed.
+pub async fn fail_interrupted_jobs(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let error = SpeechError::Interrupted;
+    let mut tx = db.begin().await?;
+
+    sqlx::query(
+        r#"UPDATE speech_chunks c
+        SET status = $1, updated_at = CURRENT_TIMESTAMP
+        FROM speech_jobs j
+        WHERE c.job_id = j.id AND c.status = $2 AND j.status IN ($3, $4)"#,
+    )
+    .bind(ChunkState::Pending.as_str())
+    .bind(ChunkState::Running.as_str())
+    .bind(JobState::Queued.as_str())
+    .bind(JobState::Running.as_str())
+    .execute(&mut *tx)
+    .await?;
+
+    let res = sqlx::query(
+        r#"UPDATE speech_jobs
+        SET status = $1, error = $2, error_code = $3, updated_at = CURRENT_TIMESTAMP,
+            finished_at = CURRENT_TIMESTAMP
+        WHERE status IN ($4, $5)"#,
+    )
+    .bind(JobState::Failed.as_str())
+    .bind(error.to_string())
+    .bind(error.code())
+    .bind(JobState::Queued.as_str())
+    .bind(JobState::Running.as_str())
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(res.rows_affected())
+}
+
+/// Moves a failed or cancelled job back to `queued` so it can be resumed.
+/// Returns `false` if the job does not exist or is in neither state, which
+/// also keeps two resumes of the same job from running at once.
+pub async fn requeue_job(db: impl PgExecutor<'_>, id: Uuid) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        r#"UPDATE speech_jobs
+        SET status = $1, error = NULL, error_code = NULL, updated_at = CURRENT_TIMESTAMP,
+            finished_at = NULL, expired_at = NULL
+        WHERE id = $2 AND status IN ($3, $4)"#,
+    )
+    .bind(JobState::Queued.as_str())
+    .bind(id)
+    .bind(JobState::Failed.as_str())
+    .bind(JobState::Cancelled.as_str())
+    .execute(db)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+/// Marks a queued job running. Returns `false` if it is no longer queued,
+/// because it was cancelled before its pipeline got this far.
+pub async fn set_job_running(
+    db: &PgPool,
+    id: Uuid,
+    output_prefix: &str,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        r#"UPDATE speech_jobs
+        SET status = $1, output_prefix = $2, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $3 AND status = $4"#,
+    )
+    .bind(JobState::Running.as_str())
+    .bind(output_prefix)
+    .bind(id)
+    .bind(JobState::Queued.as_str())
+    .execute(db)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+/// Marks a job done with its merged audio. Returns `false` if the job was
+/// cancelled in the meantime, in which case it is left alone.
+pub async fn finish_job(
+    db: &PgPool,
+    id: Uuid,
+    merged_key: &str,
+    merged_bytes: i64,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        r#"UPDATE speech_jobs
+        SET status = $1, merged_key = $2, merged_bytes = $3, error = NULL, error_code = NULL,
+            updated_at = CURRENT_TIMESTAMP, finished_at = CURRENT_TIMESTAMP
+        WHERE id = $4 AND status <> $5"#,
+    )
+    .bind(JobState::Done.as_str())
+    .bind(merged_key)
+    .bind(merged_bytes)
+    .bind(id)
+    .bind(JobState::Cancelled.as_str())
+    .execute(db)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+/// Marks a job failed, unless it was cancelled in the meantime.
+pub async fn fail_job(db: &PgPool, id: Uuid, error: &SpeechError) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE speech_jobs
+        SET status = $1, error = $2, error_code = $3, updated_at = CURRENT_TIMESTAMP,
+            finished_at = CURRENT_TIMESTAMP
+        WHERE id = $4 AND status <> $5"#,
+    )
+    .bind(JobState::Failed.as_str())
+    .bind(error.to_string())
+    .bind(error.code())
+    .bind(id)
+    .bind(JobState::Cancelled.as_str())
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks a queued or running job cancelled. Returns `false` if the job does
+/// not exist or has already stopped.
+pub async fn cancel_job(db: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        r#"UPDATE speech_jobs
+        SET status = $1, updated_at = CURRENT_TIMESTAMP, finished_at = CURRENT_TIMESTAMP
+        WHERE id = $2 AND status IN ($3, $4)"#,
+    )
+    .bind(JobState::Cancelled.as_str())
+    .bind(id)
+    .bind(JobState::Queued.as_str())
+    .bind(JobState::Running.as_str())
+    .execute(db)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+/// Puts a stopped job's chunks back to `pending` and forgets their audio,
+/// except for finished chunks when `keep_done` is set.
+pub async fn reset_chunks(db: &PgPool, job_id: Uuid, keep_done: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET status = $1, object_key = NULL, bytes = NULL, cached = false, error = NULL,
+            error_code = NULL, updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $2 AND NOT (status = $3 AND $4)"#,
+    )
+    .bind(ChunkState::Pending.as_str())
+    .bind(job_id)
+    .bind(ChunkState::Done.as_str())
+    .bind(keep_done)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_chunk_running(db: &PgPool, job_id: Uuid, idx: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET status = $1, error = NULL, error_code = NULL, retries = 0,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $2 AND idx = $3"#,
+    )
+    .bind(ChunkState::Running.as_str())
+    .bind(job_id)
+    .bind(idx)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_chunk_done(
+    db: &PgPool,
+    job_id: Uuid,
+    idx: i32,
+    object_key: &str,
+    bytes: i64,
+    cached: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET status = $1, object_key = $2, bytes = $3, cached = $4, error = NULL,
+            error_code = NULL, updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $5 AND idx = $6"#,
+    )
+    .bind(ChunkState::Done.as_str())
+    .bind(object_key)
+    .bind(bytes)
+    .bind(cached)
+    .bind(job_id)
+    .bind(idx)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_chunk_failed(
+    db: &PgPool,
+    job_id: Uuid,
+    idx: i32,
+    error: &SpeechError,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET status = $1, error = $2, error_code = $3, updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $4 AND idx = $5"#,
+    )
+    .bind(ChunkState::Failed.as_str())
+    .bind(error.to_string())
+    .bind(error.code())
+    .bind(job_id)
+    .bind(idx)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Counts a retry of a chunk's TTS request.
+pub async fn record_chunk_retry(db: &PgPool, job_id: Uuid, idx: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET retries = retries + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $1 AND idx = $2"#,
+    )
+    .bind(job_id)
+    .bind(idx)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Forgets the storage keys of a job's chunks as part of `tx`, as long as
+/// the job is still done with merged audio `merged_key`. The job stays
+/// locked until `tx` ends, so it can't be started again with the chunks'
+/// audio while the objects are deleted. Returns `false`, changing nothing,
+/// if the job has moved on.
+pub async fn clear_chunk_keys(
+    tx: &mut PgConnection,
+    job_id: Uuid,
+    merged_key: &str,
+) -> Result<bool, sqlx::Error> {
+    let locked: Option<Uuid> = sqlx::query_scalar(
+        r#"SELECT id FROM speech_jobs
+        WHERE id = $1 AND status = $2 AND merged_key = $3
+        FOR UPDATE"#,
+    )
+    .bind(job_id)
+    .bind(JobState::Done.as_str())
+    .bind(merged_key)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if locked.is_none() {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET object_key = NULL, updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $1"#,
+    )
+    .bind(job_id)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(true)
+}
+
+/// Records as part of `tx` that a job's audio is deleted for being past
+/// retention, as long as the job is still the one that finished at
+/// `finished_at`. Like [`clear_chunk_keys`], it keeps the job locked until
+/// `tx` ends and returns `false` if the job has been started again since.
+pub async fn expire_job(
+    tx: &mut PgConnection,
+    id: Uuid,
+    finished_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        r#"UPDATE speech_jobs
+        SET merged_key = NULL, expired_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND status IN ($2, $3, $4) AND expired_at IS NULL AND finished_at = $5"#,
+    )
+    .bind(id)
+    .bind(JobState::Done.as_str())
+    .bind(JobState::Failed.as_str())
+    .bind(JobState::Cancelled.as_str())
+    .bind(finished_at)
+    .execute(&mut *tx)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"UPDATE speech_chunks
+        SET object_key = NULL, updated_at = CURRENT_TIMESTAMP
+        WHERE job_id = $1"#,
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(true)
+}
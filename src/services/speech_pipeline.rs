@@ -0,0 +1,495 @@
+use crate::services::adaptive_limiter::LimiterPermit;
+use crate::services::speech_cache::{self, CacheParams};
+use crate::services::speech_error::SpeechError;
+use crate::services::speech_events::JobEvent;
+use crate::services::speech_jobs::{ChunkWork, JobState};
+use crate::services::storage::AudioStorage;
+use crate::services::tts_service::{SynthesisRequest, TtsError, TtsProvider, TTS_FORMAT};
+use crate::services::{plans, speech_jobs, usage, webhooks};
+use crate::state::AppState;
+use crate::utils::concat_mp3::concat_mp3;
+use crate::utils::output_paths;
+use futures::future::join_all;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Semaphore};
+use tokio::task;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Opens the job's event channel and runs the pipeline in the background.
+/// The channel is opened before this returns, so a client that subscribes
+/// right after getting the job ID does not miss the start of the stream.
+/// The returned receiver sees every event of this run.
+pub fn spawn_speech_job(state: AppState, job_id: Uuid) -> broadcast::Receiver<JobEvent> {
+    let events = state.job_events.open(job_id).subscribe();
+    task::spawn(run_speech_job(state, job_id));
+    events
+}
+
+/// Runs the chunk -> TTS -> merge pipeline for a job that was already
+/// recorded with `speech_jobs::insert_job`. Progress and the final outcome
+/// are written to the job's rows and broadcast as `JobEvent`s; nothing is
+/// returned to the caller.
+///
+/// Only chunks that are not yet done (or whose file has gone missing) are
+/// synthesized, so the same function starts a fresh job, resumes a failed
+/// one, and re-runs a resubmitted one with only its changed chunks. If any
+/// chunk fails, the chunks that succeeded are kept and the job is marked
+/// failed without merging.
+///
+/// At most the plan's `max_concurrency` chunks of this job are synthesized
+/// at once, and across all jobs the shared `AdaptiveLimiter` backs off when
+/// the provider answers with 429 or 5xx. The merge always follows chunk
+/// indexes, so the output does not depend on the order in which chunks finish.
+///
+/// Cancelling the job through `JobEvents::cancel` aborts its chunk tasks,
+/// in flight or not; the job's rows are left to whoever cancelled it.
+pub async fn run_speech_job(state: AppState, job_id: Uuid) {
+    let events = state.job_events.open(job_id);
+    let cancel = state.job_events.cancel_token(job_id);
+
+    let complete = match run_pipeline(&state, job_id, &events, &cancel).await {
+        Ok((merged_path, merged_bytes)) => {
+            match speech_jobs::finish_job(&state.db, job_id, &merged_path, merged_bytes).await {
+                Ok(true) => {
+                    println!(
+                        "[Job {job_id}] All chunks processed + merged => {}",
+                        merged_path
+                    );
+                    JobEvent::JobComplete {
+                        state: JobState::Done,
+                        merged_file: Some(merged_path),
+                        error: None,
+                        error_code: None,
+                    }
+                }
+                // Cancelled while merging; the merged audio is not wanted
+                Ok(false) => {
+                    println!("[Job {job_id}] Cancelled; discarding {merged_path}");
+                    if let Err(e) = state.storage.delete(&merged_path).await {
+                        println!("[Job {job_id}] Failed to delete {merged_path}: {e}");
+                    }
+                    cancelled()
+                }
+                Err(e) => {
+                    log_db_error(job_id, Err(e));
+                    JobEvent::JobComplete {
+                        state: JobState::Done,
+                        merged_file: Some(merged_path),
+                        error: None,
+                        error_code: None,
+                    }
+                }
+            }
+        }
+        Err(SpeechError::Cancelled) => {
+            println!("[Job {job_id}] Cancelled");
+            cancelled()
+        }
+        Err(e) => {
+            println!("[Job {job_id}] {e}");
+            log_db_error(job_id, speech_jobs::fail_job(&state.db, job_id, &e).await);
+            JobEvent::JobComplete {
+                state: JobState::Failed,
+                merged_file: None,
+                error: Some(e.to_string()),
+                error_code: Some(e.code().to_string()),
+            }
+        }
+    };
+
+    // Sending only fails when nobody is listening, which is fine
+    let _ = events.send(complete);
+    state.job_events.close(job_id);
+
+    webhooks::notify_job_complete(state, job_id).await;
+}
+
+fn cancelled() -> JobEvent {
+    JobEvent::JobComplete {
+        state: JobState::Cancelled,
+        merged_file: None,
+        error: None,
+        error_code: None,
+    }
+}
+
+/// The pipeline proper. Returns the merged file and its size, or the
+/// error the job should be failed with.
+async fn run_pipeline(
+    state: &AppState,
+    job_id: Uuid,
+    events: &broadcast::Sender<JobEvent>,
+    cancel: &CancellationToken,
+) -> Result<(String, i64), SpeechError> {
+    let db = &state.db;
+    let job = speech_jobs::fetch_job(db, job_id)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load job: {e}")))?
+        .ok_or_else(|| SpeechError::Internal("Job vanished before it could start".to_string()))?;
+
+    // 1) Reuse the job's key prefix when resuming, otherwise pick a unique
+    //    one in the owner's namespace
+    let storage = state.storage.clone();
+    let prefix = match job.output_prefix {
+        Some(prefix) if output_paths::is_safe_key(&prefix) => prefix,
+        Some(prefix) => {
+            return Err(SpeechError::Storage(format!(
+                "Job has an invalid storage prefix {prefix:?}"
+            )))
+        }
+        None => output_paths::job_prefix(job.user_id, job_id, job.created_at),
+    };
+
+    println!("[Job {job_id}] Writing audio under {prefix}");
+    match speech_jobs::set_job_running(db, job_id, &prefix).await {
+        Ok(true) => {}
+        // Cancelled between submission and now; the row is already final
+        Ok(false) => return Err(SpeechError::Cancelled),
+        Err(e) => println!("[Job {job_id}] Failed to record progress: {e}"),
+    }
+
+    let chunks = speech_jobs::fetch_chunk_work(db, job_id)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load chunks: {e}")))?;
+    let chunks_total = chunks.len();
+    let mut missing = Vec::new();
+    for chunk in chunks {
+        if !is_stored(storage.as_ref(), &chunk).await {
+            missing.push(chunk);
+        }
+    }
+
+    let plan = plans::plan_for_user(db, job.user_id)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load plan: {e}")))?;
+    let job_permits = Arc::new(Semaphore::new(plan.max_concurrency as usize));
+
+    // 2) For each missing chunk, spawn a TTS task; the semaphores decide how
+    //    many of them actually run at once
+    println!(
+        "[Job {job_id}] Spawning TTS tasks (plan {}: {} at a time)...",
+        plan.name, plan.max_concurrency
+    );
+    let settings = ChunkSettings {
+        job_id,
+        user_id: job.user_id,
+        voice: job.voice.clone(),
+        model: job.model.clone(),
+        speed: job.speed,
+    };
+    let mut tasks = Vec::new();
+    let mut task_keys = Vec::new();
+    for chunk in missing {
+        let db_cloned = db.clone();
+        let events = events.clone();
+        let job_permits = job_permits.clone();
+        let task_state = state.clone();
+        let settings = settings.clone();
+        let user_id = job.user_id;
+        let index = chunk.idx;
+
+        // Construct the storage key for this chunk
+        let chunk_key = chunk_key(&prefix, job.revision, index);
+        task_keys.push(chunk_key.clone());
+
+        tasks.push(task::spawn(async move {
+            let db = db_cloned;
+            // The semaphore is never closed, so acquiring cannot fail
+            let _job_permit = job_permits.acquire_owned().await.unwrap();
+
+            log_db_error(
+                job_id,
+                speech_jobs::set_chunk_running(&db, job_id, index).await,
+            );
+            let _ = events.send(JobEvent::ChunkStarted { index });
+
+            let result = match &chunk.text {
+                Some(text) => chunk_audio(&task_state, index, text, &settings).await,
+                None => Err(SpeechError::Internal(format!(
+                    "Chunk {index} has no stored text"
+                ))),
+            };
+            // Only what was actually sent to the provider counts towards usage
+            if let (Ok((_, false)), Some(user_id), Some(text)) = (&result, user_id, &chunk.text) {
+                let chars = text.chars().count() as i32;
+                log_db_error(
+                    job_id,
+                    usage::record(&db, user_id, job_id, index, chars).await,
+                );
+            }
+            let result = match result {
+                Ok((audio, cached)) => {
+                    let bytes = audio.len() as i64;
+                    task_state
+                        .storage
+                        .put(&chunk_key, audio)
+                        .await
+                        .map(|_| (bytes, cached))
+                        .map_err(|e| {
+                            SpeechError::Storage(format!("Failed to store {chunk_key}: {e}"))
+                        })
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok((bytes, cached)) => {
+                    println!("  -> [Task {index}] wrote {chunk_key}");
+                    log_db_error(
+                        job_id,
+                        speech_jobs::set_chunk_done(&db, job_id, index, &chunk_key, bytes, cached)
+                            .await,
+                    );
+                    let _ = events.send(JobEvent::ChunkFinished {
+                        index,
+                        file: chunk_key,
+                        bytes,
+                        cached,
+                    });
+                    Ok(())
+                }
+                Err(e) => {
+                    println!("  -> [Task {index}] error: {e}");
+                    log_db_error(
+                        job_id,
+                        speech_jobs::set_chunk_failed(&db, job_id, index, &e).await,
+                    );
+                    let _ = events.send(JobEvent::ChunkFailed {
+                        index,
+                        error: e.to_string(),
+                        error_code: e.code().to_string(),
+                    });
+                    Err(e)
+                }
+            }
+        }));
+    }
+
+    // 3) Wait for all tasks
+    println!(
+        "[Job {job_id}] {} of {} chunk(s) to synthesize; waiting on join_all...",
+        tasks.len(),
+        chunks_total
+    );
+    let aborts: Vec<_> = tasks.iter().map(|task| task.abort_handle()).collect();
+    let mut all = join_all(tasks);
+    let finished = tokio::select! {
+        results = &mut all => Some(results),
+        _ = cancel.cancelled() => None,
+    };
+    let Some(results) = finished else {
+        println!("[Job {job_id}] Cancelled; aborting TTS tasks...");
+        for abort in &aborts {
+            abort.abort();
+        }
+        // A task aborted halfway through storing its chunk may have left
+        // part of the object behind
+        for (result, key) in all.await.into_iter().zip(&task_keys) {
+            if result.is_err() {
+                if let Err(e) = storage.delete(key).await {
+                    println!("[Job {job_id}] Failed to delete {key}: {e}");
+                }
+            }
+        }
+        return Err(SpeechError::Cancelled);
+    };
+    println!("[Job {job_id}] join_all completed; analyzing results...");
+
+    // Keep the chunk cache within budget now that this job has added to it
+    let cache_max_bytes = state.speech_config.cache_max_bytes;
+    if cache_max_bytes > 0 {
+        match speech_cache::evict(db, storage.as_ref(), cache_max_bytes).await {
+            Ok(0) => {}
+            Ok(n) => println!("[Job {job_id}] Evicted {n} cached chunk(s)"),
+            Err(e) => println!("[Job {job_id}] Failed to evict cached chunks: {e}"),
+        }
+    }
+
+    let mut failures = Vec::new();
+    for result in results {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => failures.push(e),
+            Err(join_err) => {
+                println!("[Job {job_id}] Task panicked or cancelled => {join_err}");
+                failures.push(SpeechError::Internal(format!("Join error: {join_err}")));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(SpeechError::ChunksFailed {
+            failed: failures.len(),
+            total: chunks_total,
+            first: Box::new(failures.swap_remove(0)),
+        });
+    }
+
+    if cancel.is_cancelled() {
+        return Err(SpeechError::Cancelled);
+    }
+
+    // 4) Now we do a naive merge of all the chunk MP3s, in order. Chunks
+    //    carried over from an earlier revision keep their earlier keys, so
+    //    the keys come from the chunk rows
+    let saved_files: Vec<String> = speech_jobs::fetch_chunk_work(db, job_id)
+        .await
+        .map_err(|e| SpeechError::Internal(format!("Failed to load chunks: {e}")))?
+        .iter()
+        .map(|chunk| {
+            chunk.done_key().map(str::to_string).ok_or_else(|| {
+                SpeechError::Merge(format!("Chunk {} has no audio to merge", chunk.idx))
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let merged_key = format!("{prefix}/speech-merged.mp3");
+    println!(
+        "[Job {job_id}] Merging {} chunk(s) => {}",
+        saved_files.len(),
+        merged_key
+    );
+    let _ = events.send(JobEvent::MergeStarted {
+        chunks: saved_files.len(),
+    });
+    let saved_files_ref: Vec<&str> = saved_files.iter().map(|s| s.as_str()).collect();
+    let merged_bytes = concat_mp3(storage.as_ref(), &saved_files_ref, &merged_key)
+        .await
+        .map_err(|e| SpeechError::Merge(format!("Failed to merge mp3: {e}")))?
+        as i64;
+
+    Ok((merged_key, merged_bytes))
+}
+
+/// Later revisions get their own keys, since a chunk carried over from an
+/// earlier one may have moved to an index whose old key it still uses.
+fn chunk_key(prefix: &str, revision: i32, index: i32) -> String {
+    match revision {
+        1 => format!("{prefix}/speech-chunk-{index}.mp3"),
+        _ => format!("{prefix}/speech-chunk-{index}.r{revision}.mp3"),
+    }
+}
+
+/// Whether a chunk finished earlier and its audio is still in storage, so
+/// it needn't be synthesized (and paid for) again.
+async fn is_stored(storage: &dyn AudioStorage, chunk: &ChunkWork) -> bool {
+    match chunk.done_key() {
+        Some(key) => storage.exists(key).await.unwrap_or(false),
+        None => false,
+    }
+}
+
+/// What every chunk of a job is synthesized with, and whose turn it takes
+/// in the shared TTS limiter.
+#[derive(Clone)]
+struct ChunkSettings {
+    job_id: Uuid,
+    user_id: Option<i32>,
+    voice: String,
+    model: String,
+    speed: f32,
+}
+
+/// A chunk's audio, from the chunk cache if it has a hit and from the
+/// provider otherwise; only provider calls wait on the shared TTS limiter,
+/// which takes turns between users and their jobs.
+///
+/// Provider errors that may clear up are retried as `tts_retry` says, with
+/// each retry counted on the chunk. The limiter slot is given back while
+/// waiting to retry, so other chunks can use it.
+/// Returns the audio and whether it came from the cache.
+async fn chunk_audio(
+    state: &AppState,
+    index: i32,
+    text: &str,
+    settings: &ChunkSettings,
+) -> Result<(Vec<u8>, bool), SpeechError> {
+    let db = &state.db;
+    let storage = state.storage.as_ref();
+    let hash = (state.speech_config.cache_max_bytes > 0).then(|| {
+        CacheParams {
+            provider: state.tts.name(),
+            text,
+            voice: &settings.voice,
+            model: &settings.model,
+            format: TTS_FORMAT,
+            speed: settings.speed,
+        }
+        .hash()
+    });
+
+    if let Some(hash) = &hash {
+        if let Some(audio) = speech_cache::lookup(db, storage, hash).await {
+            println!("  -> [Task {index}] cache hit");
+            return Ok((audio, true));
+        }
+    }
+
+    let job_id = settings.job_id;
+    let mut attempt = 1;
+    let audio = loop {
+        let permit = state.tts_limiter.acquire(settings.user_id, job_id).await;
+        println!("  -> [Task {index}] calling TTS ({})...", state.tts.name());
+        let result = synthesize_chunk(state.tts.as_ref(), &permit, text, settings).await;
+        drop(permit);
+
+        let mut error = match result {
+            Ok(audio) => break audio,
+            Err(e) => e,
+        };
+        let Some(delay) = state.speech_config.tts_retry.retry_delay(attempt, &error) else {
+            if attempt > 1 {
+                error.message = format!("{} (after {attempt} attempts)", error.message);
+            }
+            return Err(error.into());
+        };
+
+        println!("  -> [Task {index}] attempt {attempt} failed ({error}); retrying in {delay:?}");
+        log_db_error(
+            job_id,
+            speech_jobs::record_chunk_retry(db, job_id, index).await,
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
+    if let Some(hash) = &hash {
+        speech_cache::insert(db, storage, hash, TTS_FORMAT, &audio).await;
+    }
+    Ok((audio, false))
+}
+
+/// Synthesizes one chunk. The provider's answer is fed back into the
+/// adaptive limit via `permit`.
+async fn synthesize_chunk(
+    tts: &dyn TtsProvider,
+    permit: &LimiterPermit,
+    text: &str,
+    settings: &ChunkSettings,
+) -> Result<Vec<u8>, TtsError> {
+    let request = SynthesisRequest {
+        text,
+        voice: &settings.voice,
+        model: &settings.model,
+        format: TTS_FORMAT,
+        speed: settings.speed,
+    };
+    match tts.synthesize(&request).await {
+        Ok(bytes) => {
+            permit.success();
+            Ok(bytes)
+        }
+        Err(e) => {
+            if e.is_overload() {
+                permit.overload();
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Bookkeeping failures are logged rather than aborting the synthesis itself.
+fn log_db_error(job_id: Uuid, result: Result<(), sqlx::Error>) {
+    if let Err(e) = result {
+        println!("[Job {job_id}] Failed to record progress: {e}");
+    }
+}
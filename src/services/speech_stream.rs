@@ -0,0 +1,91 @@
+use crate::services::speech_events::JobEvent;
+use crate::services::speech_jobs::{self, ChunkState, JobState};
+use crate::services::storage::AudioStorage;
+use crate::utils::concat_mp3::strip_id3_tags;
+use async_stream::try_stream;
+use futures::Stream;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// The merged audio of a running job, produced as it is synthesized.
+///
+/// Chunk N is yielded (with its ID3 tags stripped, as `concat_mp3` does)
+/// as soon as chunks 1..=N have all finished, so the concatenation of
+/// everything yielded matches the merged file. The stream errors out if a
+/// chunk fails, since the audio can no longer be gapless.
+///
+/// Chunks that finished before the stream started are looked up in the
+/// database, as are finished chunks whose events were missed because
+/// `events` lagged behind. For a job that is no longer running, `events`
+/// can be a closed receiver; the stream is then just the stored audio.
+pub fn merged_audio_stream(
+    db: PgPool,
+    storage: Arc<dyn AudioStorage>,
+    job_id: Uuid,
+    mut events: broadcast::Receiver<JobEvent>,
+) -> impl Stream<Item = io::Result<Vec<u8>>> {
+    try_stream! {
+        let mut finished: BTreeMap<i32, String> =
+            finished_chunks(&db, job_id).await?.into_iter().collect();
+        let mut next = 1;
+
+        loop {
+            let complete = match events.recv().await {
+                Ok(JobEvent::ChunkFinished { index, file, .. }) => {
+                    finished.insert(index, file);
+                    false
+                }
+                Ok(JobEvent::ChunkFailed { index, error, .. }) => {
+                    Err(io::Error::other(format!("Chunk {index} failed: {error}")))?;
+                    true
+                }
+                Ok(JobEvent::JobComplete { state, error, .. }) => {
+                    if state != JobState::Done {
+                        let error = error.unwrap_or_default();
+                        Err(io::Error::other(format!("Speech job failed: {error}")))?;
+                    }
+                    true
+                }
+                Ok(_) => false,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    finished.extend(finished_chunks(&db, job_id).await?);
+                    false
+                }
+                Err(broadcast::error::RecvError::Closed) => true,
+            };
+
+            if complete {
+                // Pick up anything whose event we may have missed
+                finished.extend(finished_chunks(&db, job_id).await?);
+            }
+
+            while let Some(key) = finished.remove(&next) {
+                let bytes = storage.get(&key).await?;
+                yield strip_id3_tags(&bytes).to_vec();
+                next += 1;
+            }
+
+            if complete {
+                break;
+            }
+        }
+    }
+}
+
+async fn finished_chunks(db: &PgPool, job_id: Uuid) -> io::Result<Vec<(i32, String)>> {
+    let job = speech_jobs::fetch_job(db, job_id)
+        .await
+        .map_err(io::Error::other)?
+        .ok_or_else(|| io::Error::other(format!("Speech job {job_id} vanished")))?;
+
+    Ok(job
+        .chunks
+        .into_iter()
+        .filter(|c| c.state == ChunkState::Done)
+        .filter_map(|c| Some((c.index, c.file?)))
+        .collect())
+}
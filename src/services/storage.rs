@@ -0,0 +1,366 @@
+use axum::body::Bytes;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::{path::Path as ObjectPath, GetOptions, GetRange, ObjectStore, WriteMultipart};
+use std::io::{self, ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio_util::io::ReaderStream;
+
+use crate::config::StorageConfig;
+use crate::utils::output_paths::is_safe_key;
+
+/// A stream of an object's bytes, as served to clients.
+pub type ByteStream = BoxStream<'static, io::Result<Bytes>>;
+
+/// What `Content-Length` and `ETag` are built from.
+pub struct ObjectInfo {
+    pub size: u64,
+    /// A quoted entity tag that changes whenever the object is rewritten.
+    pub etag: String,
+}
+
+/// Where generated audio is kept. Keys are `/`-separated and relative to
+/// the store's root (a directory or a bucket); keys that could escape it
+/// are rejected with `InvalidInput`. Missing objects are `NotFound`.
+#[axum::async_trait]
+pub trait AudioStorage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()>;
+
+    /// Writes an object piece by piece, for objects too large to hold in
+    /// memory. The object only appears under `key` once the writer is
+    /// finished.
+    async fn writer(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>>;
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+
+    /// Streams the bytes in `range`, which must lie within the object.
+    async fn get_range(&self, key: &str, range: Range<u64>) -> io::Result<ByteStream>;
+
+    async fn head(&self, key: &str) -> io::Result<ObjectInfo>;
+
+    /// Deletes an object. Deleting one that is already gone is not an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        match self.head(key).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// An object being written by `AudioStorage::writer`.
+#[axum::async_trait]
+pub trait ObjectWriter: Send {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Stores what was written under the key. Returns the object's size.
+    async fn finish(self: Box<Self>) -> io::Result<u64>;
+
+    /// Throws away what was written so far.
+    async fn abort(self: Box<Self>) -> io::Result<()>;
+}
+
+/// Builds the store selected by `SPEECH_STORAGE`.
+pub fn from_config(config: &StorageConfig) -> Arc<dyn AudioStorage> {
+    match config {
+        StorageConfig::Local { root } => Arc::new(LocalStorage::new(root.clone())),
+        StorageConfig::S3 {
+            bucket,
+            region,
+            endpoint,
+            access_key_id,
+            secret_access_key,
+        } => {
+            let mut builder = AmazonS3Builder::new()
+                .with_bucket_name(bucket)
+                .with_region(region);
+            if let Some(endpoint) = endpoint {
+                // S3-compatible servers such as MinIO are usually plain HTTP
+                // on localhost
+                builder = builder
+                    .with_endpoint(endpoint)
+                    .with_allow_http(endpoint.starts_with("http://"));
+            }
+            if let Some(id) = access_key_id {
+                builder = builder.with_access_key_id(id);
+            }
+            if let Some(secret) = secret_access_key {
+                builder = builder.with_secret_access_key(secret);
+            }
+            let store = builder
+                .build()
+                .unwrap_or_else(|e| panic!("Invalid S3 storage settings: {e}"));
+            Arc::new(S3Storage { store })
+        }
+    }
+}
+
+/// Objects as files under a directory on the local disk.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[axum::async_trait]
+impl AudioStorage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(path, data).await
+    }
+
+    async fn writer(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut partial = path.clone().into_os_string();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        let file = File::create(&partial).await?;
+        Ok(Box::new(LocalWriter {
+            file: BufWriter::new(file),
+            partial,
+            path,
+            written: 0,
+        }))
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(key)?).await
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> io::Result<ByteStream> {
+        let mut file = File::open(self.path(key)?).await?;
+        if range.start > 0 {
+            file.seek(SeekFrom::Start(range.start)).await?;
+        }
+        let count = range.end - range.start;
+        Ok(ReaderStream::new(file.take(count)).boxed())
+    }
+
+    async fn head(&self, key: &str) -> io::Result<ObjectInfo> {
+        let meta = fs::metadata(self.path(key)?).await?;
+        if !meta.is_file() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("{key} is not a file"),
+            ));
+        }
+
+        // Files are only ever rewritten whole, so size and mtime are enough
+        // to tell versions apart
+        let size = meta.len();
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Ok(ObjectInfo {
+            size,
+            etag: format!("\"{size:x}-{mtime:x}\""),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key)?;
+        match fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        // Drop the job's folder along with its last file; this fails, as it
+        // should, while the folder still has anything in it
+        if let Some(dir) = path.parent() {
+            let _ = fs::remove_dir(dir).await;
+        }
+        Ok(())
+    }
+}
+
+/// Writes to a `.part` file next to the object and renames it into place
+/// when finished, so readers never see a half-written file.
+struct LocalWriter {
+    file: BufWriter<File>,
+    partial: PathBuf,
+    path: PathBuf,
+    written: u64,
+}
+
+#[axum::async_trait]
+impl ObjectWriter for LocalWriter {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data).await?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> io::Result<u64> {
+        self.file.flush().await?;
+        fs::rename(&self.partial, &self.path).await?;
+        Ok(self.written)
+    }
+
+    async fn abort(self: Box<Self>) -> io::Result<()> {
+        drop(self.file);
+        match fs::remove_file(&self.partial).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Objects in an S3 bucket, or in any server speaking the S3 API.
+pub struct S3Storage {
+    store: AmazonS3,
+}
+
+impl S3Storage {
+    fn location(key: &str) -> io::Result<ObjectPath> {
+        check_key(key)?;
+        ObjectPath::parse(key).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+    }
+}
+
+#[axum::async_trait]
+impl AudioStorage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let location = Self::location(key)?;
+        self.store
+            .put(&location, data.into())
+            .await
+            .map_err(object_store_error)?;
+        Ok(())
+    }
+
+    async fn writer(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        let location = Self::location(key)?;
+        let upload = self
+            .store
+            .put_multipart(&location)
+            .await
+            .map_err(object_store_error)?;
+        Ok(Box::new(S3Writer {
+            upload: WriteMultipart::new(upload),
+            written: 0,
+        }))
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let location = Self::location(key)?;
+        let object = self
+            .store
+            .get(&location)
+            .await
+            .map_err(object_store_error)?;
+        let bytes = object.bytes().await.map_err(object_store_error)?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> io::Result<ByteStream> {
+        let location = Self::location(key)?;
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(range.start as usize..range.end as usize)),
+            ..Default::default()
+        };
+        let object = self
+            .store
+            .get_opts(&location, options)
+            .await
+            .map_err(object_store_error)?;
+        Ok(object.into_stream().map_err(object_store_error).boxed())
+    }
+
+    async fn head(&self, key: &str) -> io::Result<ObjectInfo> {
+        let location = Self::location(key)?;
+        let meta = self
+            .store
+            .head(&location)
+            .await
+            .map_err(object_store_error)?;
+
+        let size = meta.size as u64;
+        let etag = match meta.e_tag {
+            Some(tag) => format!("\"{}\"", tag.trim_matches('"')),
+            None => format!("\"{size:x}-{:x}\"", meta.last_modified.timestamp_millis()),
+        };
+        Ok(ObjectInfo { size, etag })
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let location = Self::location(key)?;
+        match self.store.delete(&location).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(object_store_error(e)),
+        }
+    }
+}
+
+/// Uploads in parts as data comes in. At most `S3_UPLOADS_IN_FLIGHT`
+/// parts are buffered at once.
+struct S3Writer {
+    upload: WriteMultipart,
+    written: u64,
+}
+
+const S3_UPLOADS_IN_FLIGHT: usize = 4;
+
+#[axum::async_trait]
+impl ObjectWriter for S3Writer {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.upload
+            .wait_for_capacity(S3_UPLOADS_IN_FLIGHT)
+            .await
+            .map_err(object_store_error)?;
+        self.upload.write(data);
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> io::Result<u64> {
+        self.upload.finish().await.map_err(object_store_error)?;
+        Ok(self.written)
+    }
+
+    async fn abort(self: Box<Self>) -> io::Result<()> {
+        self.upload.abort().await.map_err(object_store_error)
+    }
+}
+
+fn check_key(key: &str) -> io::Result<()> {
+    if is_safe_key(key) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid storage key {key:?}"),
+        ))
+    }
+}
+
+fn object_store_error(e: object_store::Error) -> io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => io::Error::new(ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
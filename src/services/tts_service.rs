@@ -1,43 +1,364 @@
 // src/services/tts_service.rs
-use reqwest::Client;
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::TtsConfig;
+use crate::services::mock_tts::MockTts;
+
+/// Audio format requested for every chunk; the merge assumes MP3.
+pub const TTS_FORMAT: &str = "mp3";
+/// Playback speed used when a request doesn't pick one.
+pub const TTS_SPEED: f32 = 1.0;
+
+/// One chunk of text to synthesize, with the settings of its job.
+pub struct SynthesisRequest<'a> {
+    pub text: &'a str,
+    pub voice: &'a str,
+    pub model: &'a str,
+    pub format: &'a str,
+    pub speed: f32,
+}
+
+/// A text-to-speech backend. Speech requests are checked against what the
+/// provider reports before any job is created, so `synthesize` is only
+/// asked for voices, models and speeds it listed, and for texts no longer
+/// than `max_input_chars`.
+#[axum::async_trait]
+pub trait TtsProvider: Send + Sync {
+    /// Short name for logs, such as `openai`.
+    fn name(&self) -> &'static str;
+
+    async fn synthesize(&self, request: &SynthesisRequest<'_>) -> Result<Vec<u8>, TtsError>;
+
+    /// Voices a request may pick.
+    fn voices(&self) -> &[&str];
+
+    /// The voice used when a request doesn't pick one.
+    fn default_voice(&self) -> &str;
+
+    /// Models a request may pick.
+    fn models(&self) -> &[&str];
+
+    /// The model used when a request doesn't pick one.
+    fn default_model(&self) -> &str;
+
+    /// Audio formats `synthesize` can produce.
+    fn formats(&self) -> &[&str];
+
+    /// The slowest and fastest playback speeds accepted.
+    fn speed_range(&self) -> (f32, f32);
+
+    /// Longest text, in characters, a single `synthesize` call may take.
+    /// Documents are chunked to fit.
+    fn max_input_chars(&self) -> usize;
+}
+
+/// Builds the provider selected by `SPEECH_TTS_PROVIDER`.
+pub fn from_config(config: &TtsConfig) -> Arc<dyn TtsProvider> {
+    match config {
+        TtsConfig::OpenAi { api_key } => Arc::new(OpenAiTts::new(api_key.clone())),
+        TtsConfig::Mock {
+            latency,
+            failure_rate,
+            rate_limit_rate,
+        } => Arc::new(MockTts::new(*latency, *failure_rate, *rate_limit_rate)),
+    }
+}
 
 #[derive(Serialize)]
 struct TtsRequest {
     model: String,
     input: String,
     voice: String,
+    response_format: String,
+    speed: f32,
+}
+
+/// The body of an OpenAI error response.
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+    code: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TtsError {
+    /// HTTP status returned by the provider, if the request got that far.
+    pub status: Option<StatusCode>,
+    pub message: String,
+    /// Whether the same request may succeed if sent again. Timeouts,
+    /// connection failures, 408, 429 and 5xx are; anything else the
+    /// provider rejected is permanent.
+    pub retryable: bool,
+    /// How long the provider asked us to wait, from `Retry-After`.
+    pub retry_after: Option<Duration>,
+}
+
+impl TtsError {
+    pub fn new(status: Option<StatusCode>, message: String) -> Self {
+        let retryable = status.is_none_or(|s| {
+            s == StatusCode::REQUEST_TIMEOUT
+                || s == StatusCode::TOO_MANY_REQUESTS
+                || s.is_server_error()
+        });
+        Self {
+            status,
+            message,
+            retryable,
+            retry_after: None,
+        }
+    }
+
+    /// Whether the provider is telling us to slow down (429) or is
+    /// struggling (5xx), as opposed to rejecting this particular request.
+    pub fn is_overload(&self) -> bool {
+        self.status
+            .is_some_and(|s| s == StatusCode::TOO_MANY_REQUESTS || s.is_server_error())
+    }
+}
+
+impl fmt::Display for TtsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// How failed TTS requests are retried: up to `max_attempts` requests in
+/// all, with a jittered exponential backoff in between, unless the provider
+/// says how long to wait.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// The backoff before the first retry; it doubles with each retry after.
+    pub base_delay: Duration,
+    /// The longest wait between attempts. A `Retry-After` asking for more
+    /// fails the request instead.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// How long to wait before retrying after `attempt` (from 1) failed with
+    /// `error`, or `None` if it shouldn't be retried.
+    pub fn retry_delay(&self, attempt: u32, error: &TtsError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.retryable {
+            return None;
+        }
+        if let Some(wait) = error.retry_after {
+            return (wait <= self.max_delay).then_some(wait);
+        }
+
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        // Half the backoff plus a random share of the other half, so chunks
+        // that failed together don't all come back at once
+        let half = ceiling / 2;
+        Some(half + half.mul_f64(rand::thread_rng().gen()))
+    }
+}
+
+/// `Retry-After` as a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// OpenAI's `/v1/audio/speech`.
+pub struct OpenAiTts {
+    client: Client,
+    api_key: String,
+}
+
+impl OpenAiTts {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            client: Client::new(),
+            api_key,
+        }
+    }
 }
 
-pub async fn call_openai_tts(
-    api_key: &str,
-    input_text: &str,
-    voice: &str,
-) -> Result<Vec<u8>, String> {
-    let client = Client::new();
+#[axum::async_trait]
+impl TtsProvider for OpenAiTts {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    async fn synthesize(&self, request: &SynthesisRequest<'_>) -> Result<Vec<u8>, TtsError> {
+        let body = TtsRequest {
+            model: request.model.to_string(),
+            input: request.text.to_string(),
+            voice: request.voice.to_string(),
+            response_format: request.format.to_string(),
+            speed: request.speed,
+        };
+
+        let resp = self
+            .client
+            .post("https://api.openai.com/v1/audio/speech")
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| TtsError::new(e.status(), format!("Request error: {e}")))?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let wait = retry_after(resp.headers());
+            let text = resp.text().await.unwrap_or_default();
+            let detail = serde_json::from_str::<ErrorBody>(&text)
+                .ok()
+                .map(|b| b.error);
+            let message = detail
+                .as_ref()
+                .map_or(text.as_str(), |d| d.message.as_str());
 
-    let body = TtsRequest {
-        model: "tts-1".to_string(),
-        input: input_text.to_string(),
-        voice: voice.to_string(),
+            let mut error = TtsError::new(
+                Some(status),
+                format!("TTS request failed: {status} - {message}"),
+            );
+            error.retry_after = wait;
+            // A 429 for an exhausted account won't clear up by waiting
+            if detail.and_then(|d| d.code).as_deref() == Some("insufficient_quota") {
+                error.retryable = false;
+            }
+            return Err(error);
+        }
+
+        resp.bytes()
+            .await
+            .map(|b| b.to_vec())
+            .map_err(|e| TtsError::new(None, format!("Unable to read TTS response bytes: {e}")))
+    }
+
+    fn voices(&self) -> &[&str] {
+        &[
+            "alloy", "ash", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer",
+        ]
+    }
+
+    fn default_voice(&self) -> &str {
+        "onyx"
+    }
+
+    fn models(&self) -> &[&str] {
+        &["tts-1", "tts-1-hd"]
+    }
+
+    fn default_model(&self) -> &str {
+        "tts-1"
+    }
+
+    fn formats(&self) -> &[&str] {
+        &["mp3", "opus", "aac", "flac", "wav", "pcm"]
+    }
+
+    fn speed_range(&self) -> (f32, f32) {
+        (0.25, 4.0)
+    }
+
+    fn max_input_chars(&self) -> usize {
+        4096
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(30),
     };
 
-    let resp = client
-        .post("https://api.openai.com/v1/audio/speech")
-        .bearer_auth(api_key)
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| format!("Request error: {e}"))?;
+    fn error(status: u16, retry_after: Option<Duration>) -> TtsError {
+        let mut error = TtsError::new(
+            Some(StatusCode::from_u16(status).unwrap()),
+            "failed".to_string(),
+        );
+        error.retry_after = retry_after;
+        error
+    }
 
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("TTS request failed: {status} - {text}"));
+    #[test]
+    fn retry_after_is_honoured_up_to_the_max_delay() {
+        let asked = Some(Duration::from_secs(2));
+        assert_eq!(POLICY.retry_delay(1, &error(429, asked)), asked);
+
+        let at_max = Some(POLICY.max_delay);
+        assert_eq!(POLICY.retry_delay(1, &error(503, at_max)), at_max);
+
+        // Waiting longer than that fails the request instead
+        let too_long = Some(POLICY.max_delay + Duration::from_secs(1));
+        assert_eq!(POLICY.retry_delay(1, &error(429, too_long)), None);
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_and_is_capped() {
+        for (attempt, ceiling) in [(1, 500), (2, 1_000), (3, 2_000)] {
+            let ceiling = Duration::from_millis(ceiling);
+            let delay = POLICY.retry_delay(attempt, &error(500, None)).unwrap();
+            assert!(ceiling / 2 <= delay && delay <= ceiling, "{delay:?}");
+        }
+
+        let policy = RetryPolicy {
+            max_attempts: 40,
+            ..POLICY
+        };
+        let delay = policy.retry_delay(30, &error(500, None)).unwrap();
+        assert!(policy.max_delay / 2 <= delay && delay <= policy.max_delay);
     }
 
-    resp.bytes()
-        .await
-        .map(|b| b.to_vec())
-        .map_err(|e| format!("Unable to read TTS response bytes: {e}"))
+    #[test]
+    fn gives_up_on_permanent_errors_and_the_last_attempt() {
+        assert_eq!(POLICY.retry_delay(1, &error(400, None)), None);
+        assert_eq!(POLICY.retry_delay(1, &error(401, None)), None);
+        assert_eq!(
+            POLICY.retry_delay(POLICY.max_attempts, &error(503, None)),
+            None
+        );
+        assert!(POLICY.retry_delay(1, &error(408, None)).is_some());
+        let timeout = TtsError::new(None, "timed out".to_string());
+        assert!(POLICY.retry_delay(1, &timeout).is_some());
+    }
+
+    #[test]
+    fn retry_after_takes_seconds_or_a_date() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(retry_after(&headers("7")), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&headers("soon")), None);
+
+        let in_a_minute = (Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let wait = retry_after(&headers(&in_a_minute)).unwrap();
+        assert!(Duration::from_secs(58) <= wait && wait <= Duration::from_secs(60));
+        let past = (Utc::now() - chrono::Duration::seconds(60)).to_rfc2822();
+        assert_eq!(retry_after(&headers(&past)), Some(Duration::ZERO));
+    }
 }
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgExecutor};
+use uuid::Uuid;
+
+use crate::services::speech_jobs::{ChunkState, JobState};
+
+/// A user's TTS usage in the current billing period, which is the calendar
+/// month in UTC.
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    /// Characters sent to the TTS provider so far this period.
+    pub chars_used: i64,
+    /// Characters of the user's queued and running jobs not sent yet.
+    pub chars_reserved: i64,
+    pub chars_limit: i64,
+    pub chars_remaining: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct UsageRow {
+    chars_limit: i64,
+    chars_used: i64,
+    chars_reserved: i64,
+}
+
+/// The billing period `now` falls in.
+fn period(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (year, month) = (now.year(), now.month());
+    let (next_year, next_month) = match month {
+        12 => (year + 1, 1),
+        _ => (year, month + 1),
+    };
+    let start = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+    let end = Utc
+        .with_ymd_and_hms(next_year, next_month, 1, 0, 0, 0)
+        .unwrap();
+    (start, end)
+}
+
+/// Locks the user's row until the end of `tx`. Requests that check the
+/// user's quota and then reserve characters take this lock first, so that
+/// they take turns instead of all passing the check at once.
+pub async fn lock_user(tx: &mut PgConnection, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+/// The user's usage this period. Their limit is `users.monthly_chars` if
+/// set, otherwise their plan's.
+pub async fn usage_for_user(db: impl PgExecutor<'_>, user_id: i32) -> Result<Usage, sqlx::Error> {
+    let (period_start, period_end) = period(Utc::now());
+
+    let row: UsageRow = sqlx::query_as(
+        r#"SELECT COALESCE(u.monthly_chars, p.monthly_chars) AS chars_limit,
+            (SELECT COALESCE(SUM(t.chars), 0) FROM tts_usage t
+                WHERE t.user_id = u.id AND t.created_at >= $2 AND t.created_at < $3)::BIGINT AS chars_used,
+            (SELECT COALESCE(SUM(c.input_chars), 0) FROM speech_chunks c
+                JOIN speech_jobs j ON j.id = c.job_id
+                WHERE j.user_id = u.id AND j.status IN ($4, $5) AND c.status IN ($6, $7))::BIGINT AS chars_reserved
+        FROM users u
+        JOIN plans p ON p.name = u.plan
+        WHERE u.id = $1"#,
+    )
+    .bind(user_id)
+    .bind(period_start)
+    .bind(period_end)
+    .bind(JobState::Queued.as_str())
+    .bind(JobState::Running.as_str())
+    .bind(ChunkState::Pending.as_str())
+    .bind(ChunkState::Running.as_str())
+    .fetch_one(db)
+    .await?;
+
+    Ok(Usage {
+        period_start,
+        period_end,
+        chars_used: row.chars_used,
+        chars_reserved: row.chars_reserved,
+        chars_limit: row.chars_limit,
+        chars_remaining: (row.chars_limit - row.chars_used - row.chars_reserved).max(0),
+    })
+}
+
+/// Adds a chunk sent to the TTS provider to the usage ledger.
+pub async fn record(
+    db: impl PgExecutor<'_>,
+    user_id: i32,
+    job_id: Uuid,
+    chunk_idx: i32,
+    chars: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO tts_usage (user_id, job_id, chunk_idx, chars) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(user_id)
+    .bind(job_id)
+    .bind(chunk_idx)
+    .bind(chars)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
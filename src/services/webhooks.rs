@@ -0,0 +1,552 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::{redirect, Client, Url};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::lookup_host;
+use uuid::Uuid;
+
+use crate::services::speech_jobs::{self, JobState};
+use crate::state::AppState;
+
+/// How long a webhook endpoint gets to answer a delivery.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A URL registered by a user, as listed by `GET /api/webhooks`. The secret
+/// is only ever shown when the webhook is created.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A row of the delivery log.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub job_id: Option<Uuid>,
+    pub event: String,
+    /// `pending` while attempts remain, then `delivered` or `failed`.
+    pub status: String,
+    pub attempts: i32,
+    /// HTTP status of the last attempt, if the endpoint answered at all.
+    pub response_status: Option<i32>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct Target {
+    id: Uuid,
+    url: String,
+    secret: String,
+}
+
+/// A delivery that was still being retried when the server stopped.
+#[derive(sqlx::FromRow)]
+struct PendingDelivery {
+    id: Uuid,
+    event: String,
+    payload: String,
+    attempts: i32,
+    webhook_id: Uuid,
+    url: String,
+    secret: String,
+}
+
+/// Checks that a webhook URL is http(s) and that every address its host
+/// resolves to may be sent requests, so webhooks can't be used to reach
+/// the server's own network. Returns the URL and the address to connect
+/// to, or why the URL is refused.
+pub async fn resolve_url(url: &str, allow_private: bool) -> Result<(Url, SocketAddr), String> {
+    let url = Url::parse(url).map_err(|_| "must be an http or https URL".to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("must be an http or https URL".to_string());
+    }
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err("must be an http or https URL".to_string());
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(|_| format!("host {host} could not be resolved"))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("host {host} could not be resolved"));
+    }
+    if let Some(addr) = addrs.iter().find(|a| !is_allowed(a.ip(), allow_private)) {
+        return Err(format!(
+            "resolves to {}, which webhooks may not be sent to",
+            addr.ip()
+        ));
+    }
+    Ok((url, addrs[0]))
+}
+
+/// Public unicast addresses are always allowed; loopback and private
+/// network ones only with `allow_private`. Link-local addresses, where
+/// cloud metadata services live, and other special ranges never are.
+fn is_allowed(ip: IpAddr, allow_private: bool) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let shared = a == 100 && (64..128).contains(&b);
+            if ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_link_local()
+                || ip.is_documentation()
+                || a == 0
+            {
+                false
+            } else if ip.is_loopback() || ip.is_private() || shared {
+                allow_private
+            } else {
+                true
+            }
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_allowed(IpAddr::V4(v4), allow_private);
+            }
+            let first = ip.segments()[0];
+            let link_local = first & 0xffc0 == 0xfe80;
+            let unique_local = first & 0xfe00 == 0xfc00;
+            if ip.is_unspecified() || ip.is_multicast() || link_local {
+                false
+            } else if ip.is_loopback() || unique_local {
+                allow_private
+            } else {
+                true
+            }
+        }
+    }
+}
+
+/// Registers `url` for `user_id`. Returns the webhook and the secret its
+/// deliveries will be signed with.
+pub async fn create_webhook(
+    db: &PgPool,
+    user_id: i32,
+    url: &str,
+) -> Result<(Webhook, String), sqlx::Error> {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    let secret = hex::encode(key);
+
+    let webhook = sqlx::query_as(
+        r#"INSERT INTO webhooks (id, user_id, url, secret)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, url, created_at"#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(url)
+    .bind(&secret)
+    .fetch_one(db)
+    .await?;
+
+    Ok((webhook, secret))
+}
+
+pub async fn list_webhooks(db: &PgPool, user_id: i32) -> Result<Vec<Webhook>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, url, created_at FROM webhooks WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await
+}
+
+/// Deletes a webhook of `user_id` along with its delivery log. Retries
+/// still pending for it stop at their next attempt. Returns false if the
+/// user has no such webhook.
+pub async fn delete_webhook(db: &PgPool, id: Uuid, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The most recent deliveries to a webhook of `user_id`, newest first, or
+/// `None` if the user has no such webhook.
+pub async fn list_deliveries(
+    db: &PgPool,
+    webhook_id: Uuid,
+    user_id: i32,
+) -> Result<Option<Vec<WebhookDelivery>>, sqlx::Error> {
+    if fetch_target(db, webhook_id, user_id).await?.is_none() {
+        return Ok(None);
+    }
+
+    let deliveries = sqlx::query_as(
+        r#"SELECT id, webhook_id, job_id, event, status, attempts, response_status, error,
+            created_at, delivered_at
+        FROM webhook_deliveries
+        WHERE webhook_id = $1
+        ORDER BY created_at DESC
+        LIMIT 100"#,
+    )
+    .bind(webhook_id)
+    .fetch_all(db)
+    .await?;
+    Ok(Some(deliveries))
+}
+
+async fn fetch_target(
+    db: &PgPool,
+    webhook_id: Uuid,
+    user_id: i32,
+) -> Result<Option<Target>, sqlx::Error> {
+    sqlx::query_as("SELECT id, url, secret FROM webhooks WHERE id = $1 AND user_id = $2")
+        .bind(webhook_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+}
+
+/// Tells the owner's webhooks that a job finished or failed. Other
+/// outcomes, such as cancellation, aren't reported. Errors are logged; the
+/// job itself is already settled.
+pub async fn notify_job_complete(state: AppState, job_id: Uuid) {
+    if let Err(e) = queue_job_complete(&state, job_id).await {
+        println!("[Job {job_id}] Failed to queue webhook deliveries: {e}");
+    }
+}
+
+async fn queue_job_complete(state: &AppState, job_id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(mut job) = speech_jobs::fetch_job(&state.db, job_id).await? else {
+        return Ok(());
+    };
+    let event = match job.state {
+        JobState::Done => "job.done",
+        JobState::Failed => "job.failed",
+        _ => return Ok(()),
+    };
+    let Some(user_id) = job.user_id else {
+        return Ok(());
+    };
+
+    let targets: Vec<Target> =
+        sqlx::query_as("SELECT id, url, secret FROM webhooks WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&state.db)
+            .await?;
+
+    // The job's status, not its chunks; those are at its status URL
+    job.chunks.clear();
+    for target in targets {
+        let delivery_id = Uuid::new_v4();
+        let payload = json!({
+            "event": event,
+            "delivery_id": delivery_id,
+            "job": job,
+        });
+        queue_delivery(state, target, delivery_id, Some(job_id), event, payload).await?;
+    }
+    Ok(())
+}
+
+/// Sends a `ping` event to a webhook of `user_id`, so the receiving end can
+/// be tried out without running a job. Returns the delivery ID, or `None`
+/// if the user has no such webhook.
+pub async fn send_ping(
+    state: &AppState,
+    webhook_id: Uuid,
+    user_id: i32,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let Some(target) = fetch_target(&state.db, webhook_id, user_id).await? else {
+        return Ok(None);
+    };
+
+    let delivery_id = Uuid::new_v4();
+    let payload = json!({
+        "event": "ping",
+        "delivery_id": delivery_id,
+        "webhook_id": webhook_id,
+    });
+    queue_delivery(state, target, delivery_id, None, "ping", payload).await?;
+    Ok(Some(delivery_id))
+}
+
+/// Records a delivery in the log and sends it in the background.
+async fn queue_delivery(
+    state: &AppState,
+    target: Target,
+    delivery_id: Uuid,
+    job_id: Option<Uuid>,
+    event: &'static str,
+    payload: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    let body = payload.to_string();
+    sqlx::query(
+        r#"INSERT INTO webhook_deliveries (id, webhook_id, job_id, event, payload)
+        VALUES ($1, $2, $3, $4, $5)"#,
+    )
+    .bind(delivery_id)
+    .bind(target.id)
+    .bind(job_id)
+    .bind(event)
+    .bind(&body)
+    .execute(&state.db)
+    .await?;
+
+    tokio::spawn(deliver(
+        state.clone(),
+        target,
+        delivery_id,
+        event.to_string(),
+        body,
+        1,
+    ));
+    Ok(())
+}
+
+/// Picks up deliveries whose retries were cut short by a restart, going on
+/// from the attempt after the last one recorded. Errors are logged.
+pub async fn resume_pending_deliveries(state: &AppState) {
+    let pending: Vec<PendingDelivery> = match sqlx::query_as(
+        r#"SELECT d.id, d.event, d.payload, d.attempts, w.id AS webhook_id, w.url, w.secret
+        FROM webhook_deliveries d
+        JOIN webhooks w ON w.id = d.webhook_id
+        WHERE d.status = 'pending'"#,
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(pending) => pending,
+        Err(e) => {
+            println!("Failed to load pending webhook deliveries: {e}");
+            return;
+        }
+    };
+
+    if !pending.is_empty() {
+        println!("Resuming {} pending webhook deliveries", pending.len());
+    }
+    for delivery in pending {
+        let target = Target {
+            id: delivery.webhook_id,
+            url: delivery.url,
+            secret: delivery.secret,
+        };
+        let next_attempt = delivery.attempts as u32 + 1;
+        tokio::spawn(deliver(
+            state.clone(),
+            target,
+            delivery.id,
+            delivery.event,
+            delivery.payload,
+            next_attempt,
+        ));
+    }
+}
+
+/// POSTs a delivery until the endpoint answers with 2xx or the attempts run
+/// out, starting at attempt `first_attempt` and waiting
+/// `webhook_retry_base`, then twice that, and so on between attempts.
+/// Every attempt is recorded on the delivery's row; if the row is gone
+/// because the webhook was deleted, retrying stops.
+///
+/// The URL is resolved and checked again before every attempt, and the
+/// request goes to the address that was checked. Redirects are not
+/// followed; they count as failed attempts.
+async fn deliver(
+    state: AppState,
+    target: Target,
+    delivery_id: Uuid,
+    event: String,
+    body: String,
+    first_attempt: u32,
+) {
+    let max_attempts = state.speech_config.webhook_max_attempts;
+    let mut wait = state.speech_config.webhook_retry_base;
+
+    for attempt in first_attempt.min(max_attempts)..=max_attempts {
+        let timestamp = Utc::now().timestamp().to_string();
+        let result = match connect(&target.url, state.speech_config.webhook_allow_private).await {
+            Ok((client, url)) => client
+                .post(url)
+                .timeout(DELIVERY_TIMEOUT)
+                .header("content-type", "application/json")
+                .header("x-speech-event", &event)
+                .header("x-speech-delivery", delivery_id.to_string())
+                .header("x-speech-timestamp", &timestamp)
+                .header(
+                    "x-speech-signature",
+                    signature(&target.secret, &timestamp, &body),
+                )
+                .body(body.clone())
+                .send()
+                .await
+                .map_err(|e| (e.status(), format!("Request error: {e}"))),
+            Err(e) => Err((None, format!("Refused URL: {e}"))),
+        };
+
+        let (response_status, error) = match result {
+            Ok(resp) if resp.status().is_success() => (Some(resp.status()), None),
+            Ok(resp) => (Some(resp.status()), Some(format!("HTTP {}", resp.status()))),
+            Err((status, e)) => (status, Some(e)),
+        };
+        let status = match (&error, attempt == max_attempts) {
+            (None, _) => "delivered",
+            (Some(_), true) => "failed",
+            (Some(_), false) => "pending",
+        };
+
+        let recorded = sqlx::query(
+            r#"UPDATE webhook_deliveries
+            SET status = $2, attempts = $3, response_status = $4, error = $5,
+                updated_at = NOW(),
+                delivered_at = CASE WHEN $2 = 'delivered' THEN NOW() END
+            WHERE id = $1"#,
+        )
+        .bind(delivery_id)
+        .bind(status)
+        .bind(attempt as i32)
+        .bind(response_status.map(|s| s.as_u16() as i32))
+        .bind(&error)
+        .execute(&state.db)
+        .await;
+
+        match recorded {
+            Ok(r) if r.rows_affected() == 0 => {
+                println!(
+                    "[Webhook {}] Deleted; dropping delivery {delivery_id}",
+                    target.id
+                );
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => println!("[Webhook {}] Failed to record delivery: {e}", target.id),
+        }
+        let Some(error) = error else {
+            return;
+        };
+        println!(
+            "[Webhook {}] Delivery {delivery_id} attempt {attempt}/{max_attempts} failed: {error}",
+            target.id
+        );
+
+        if attempt < max_attempts {
+            tokio::time::sleep(wait).await;
+            wait *= 2;
+        }
+    }
+}
+
+/// A client that sends requests for `url` only to the address it was
+/// checked to resolve to, and doesn't follow redirects.
+async fn connect(url: &str, allow_private: bool) -> Result<(Client, Url), String> {
+    let (url, addr) = resolve_url(url, allow_private).await?;
+    let mut builder = Client::builder().redirect(redirect::Policy::none());
+    if let Some(domain) = url.domain() {
+        builder = builder.resolve(domain, addr);
+    }
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {e}"))?;
+    Ok((client, url))
+}
+
+/// The `X-Speech-Signature` of a delivery: an HMAC-SHA256 keyed with the
+/// webhook's secret over `<timestamp>.<body>`, as `sha256=<hex>`.
+pub fn signature(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_the_hmac_of_timestamp_and_body() {
+        let body = r#"{"event":"ping"}"#;
+        assert_eq!(
+            signature("whsec_test", "1700000000", body),
+            "sha256=aa8efe37b751e71157c508c5ac4acb1e9fe5225db98355dfc00f4b680afbc447"
+        );
+        // The timestamp is signed too, so a delivery can't be replayed as new
+        assert_ne!(
+            signature("whsec_test", "1700000001", body),
+            signature("whsec_test", "1700000000", body)
+        );
+        assert_ne!(
+            signature("whsec_other", "1700000000", body),
+            signature("whsec_test", "1700000000", body)
+        );
+    }
+
+    #[test]
+    fn private_addresses_need_the_opt_in() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.10",
+            "100.64.0.1",
+            "::1",
+            "fd00::1",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(!is_allowed(ip, false), "{ip}");
+            assert!(is_allowed(ip, true), "{ip}");
+        }
+    }
+
+    #[test]
+    fn special_addresses_are_always_refused() {
+        for ip in [
+            "169.254.169.254",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "192.0.2.1",
+            "fe80::1",
+            "::",
+            "::ffff:169.254.169.254",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(!is_allowed(ip, true), "{ip}");
+        }
+        assert!(is_allowed("93.184.216.34".parse().unwrap(), false));
+        assert!(is_allowed("2606:4700::1111".parse().unwrap(), false));
+    }
+
+    #[tokio::test]
+    async fn urls_are_checked_against_their_addresses() {
+        let (_, addr) = resolve_url("https://93.184.216.34/hook", false)
+            .await
+            .unwrap();
+        assert_eq!(addr, "93.184.216.34:443".parse().unwrap());
+
+        assert!(resolve_url("ftp://93.184.216.34/hook", false)
+            .await
+            .is_err());
+        assert!(resolve_url("http://127.0.0.1:9100/hook", false)
+            .await
+            .is_err());
+        assert!(resolve_url("http://127.0.0.1:9100/hook", true)
+            .await
+            .is_ok());
+        assert!(resolve_url("http://[::ffff:a9fe:a9fe]/", true)
+            .await
+            .is_err());
+    }
+}
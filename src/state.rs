@@ -2,11 +2,27 @@ use axum::extract::FromRef;
 use axum_extra::extract::cookie::Key;
 use shuttle_openai::async_openai::{config::OpenAIConfig, Client};
 use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::config::SpeechConfig;
+use crate::services::adaptive_limiter::AdaptiveLimiter;
+use crate::services::speech_events::JobEvents;
+use crate::services::storage::{self, AudioStorage};
+use crate::services::tts_service::{self, TtsProvider, TTS_FORMAT};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
     pub openai_client: Client<OpenAIConfig>,
+    pub speech_config: SpeechConfig,
+    /// Caps TTS requests in flight across every running speech job, backing
+    /// off when the provider rate-limits us.
+    pub tts_limiter: Arc<AdaptiveLimiter>,
+    pub job_events: JobEvents,
+    /// Where chunk and merged audio are written and served from.
+    pub storage: Arc<dyn AudioStorage>,
+    /// The text-to-speech backend speech jobs are synthesized with.
+    pub tts: Arc<dyn TtsProvider>,
     key: Key,
 }
 
@@ -14,12 +30,28 @@ impl AppState {
     pub async fn new(
         conn_string: String,
         openai_client: Client<OpenAIConfig>,
+        speech_config: SpeechConfig,
     ) -> Result<Self, sqlx::Error> {
+        let tts = tts_service::from_config(&speech_config.tts);
+        assert!(
+            tts.formats().contains(&TTS_FORMAT),
+            "TTS provider {} cannot produce {TTS_FORMAT}",
+            tts.name()
+        );
         let db = PgPool::connect(&conn_string).await?;
 
         Ok(Self {
             db,
             openai_client,
+            tts_limiter: Arc::new(AdaptiveLimiter::new(
+                speech_config.initial_concurrency,
+                1,
+                speech_config.max_concurrency,
+            )),
+            job_events: JobEvents::default(),
+            storage: storage::from_config(&speech_config.storage),
+            speech_config,
+            tts,
             key: Key::generate(),
         })
     }
@@ -0,0 +1,104 @@
+/// The part of a resource a `Range` request header asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range: serve the whole resource with 200.
+    Full,
+    /// Serve bytes `start..=end` with 206.
+    Partial { start: u64, end: u64 },
+    /// The range lies outside the resource: answer 416.
+    Unsatisfiable,
+}
+
+/// Interprets a `Range` header against a resource of `len` bytes.
+///
+/// Only a single `bytes=` range is supported (`a-b`, `a-` or `-n`). Headers
+/// we don't understand, including multi-range requests, are ignored as RFC
+/// 9110 allows, and the whole resource is served instead.
+pub fn parse_range(header: Option<&str>, len: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        // "-n": the last n bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        // "a-": from a to the end
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        // "a-b": inclusive, clamped to the resource
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            _ => return ByteRange::Full,
+        },
+    };
+
+    if len == 0 || start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial { start, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(start: u64, end: u64) -> ByteRange {
+        ByteRange::Partial { start, end }
+    }
+
+    #[test]
+    fn serves_everything_without_a_usable_range() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-10"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-10,20-30"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=10"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=x-10"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=20-10"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=-"), 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn bounded_ranges_are_inclusive_and_clamped() {
+        assert_eq!(parse_range(Some("bytes=0-0"), 100), partial(0, 0));
+        assert_eq!(parse_range(Some("bytes=10-19"), 100), partial(10, 19));
+        assert_eq!(parse_range(Some(" bytes= 10 - 19 "), 100), partial(10, 19));
+        assert_eq!(parse_range(Some("bytes=90-500"), 100), partial(90, 99));
+    }
+
+    #[test]
+    fn open_and_suffix_ranges() {
+        assert_eq!(parse_range(Some("bytes=40-"), 100), partial(40, 99));
+        assert_eq!(parse_range(Some("bytes=99-"), 100), partial(99, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), partial(90, 99));
+        // A suffix longer than the resource is all of it
+        assert_eq!(parse_range(Some("bytes=-500"), 100), partial(0, 99));
+    }
+
+    #[test]
+    fn ranges_outside_the_resource_are_unsatisfiable() {
+        assert_eq!(
+            parse_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range(Some("bytes=100-200"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-10"), 0), ByteRange::Unsatisfiable);
+    }
+}
@@ -28,3 +28,90 @@ pub fn chunk_text_unicode(text: &str, max_chars: usize) -> Vec<String> {
 
     results
 }
+
+/// Chunks an edited version of a document that was previously split into
+/// `previous`. Because chunks are cut at fixed lengths, inserting or deleting
+/// a single character would otherwise move every boundary after it. Instead,
+/// previous chunks that still open or close the document are kept whole, and
+/// only the edited stretch between them is chunked afresh.
+pub fn rechunk_text_unicode(text: &str, previous: &[String], max_chars: usize) -> Vec<String> {
+    let mut rest = text;
+
+    let mut head = Vec::new();
+    for chunk in previous {
+        match rest.strip_prefix(chunk.as_str()) {
+            Some(after) if !chunk.is_empty() => {
+                head.push(chunk.clone());
+                rest = after;
+            }
+            _ => break,
+        }
+    }
+
+    let mut tail = Vec::new();
+    for chunk in previous[head.len()..].iter().rev() {
+        match rest.strip_suffix(chunk.as_str()) {
+            Some(before) if !chunk.is_empty() => {
+                tail.push(chunk.clone());
+                rest = before;
+            }
+            _ => break,
+        }
+    }
+    tail.reverse();
+
+    head.extend(chunk_text_unicode(rest, max_chars));
+    head.extend(tail);
+    head
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn previous() -> Vec<String> {
+        chunk_text_unicode("aaaabbbbccccdddd", 4)
+    }
+
+    #[test]
+    fn unchanged_text_keeps_every_chunk() {
+        assert_eq!(
+            rechunk_text_unicode("aaaabbbbccccdddd", &previous(), 4),
+            previous()
+        );
+    }
+
+    #[test]
+    fn only_the_edited_stretch_is_rechunked() {
+        assert_eq!(
+            rechunk_text_unicode("aaaabbbbccXccdddd", &previous(), 4),
+            ["aaaa", "bbbb", "ccXc", "c", "dddd"]
+        );
+        assert_eq!(
+            rechunk_text_unicode("aaaabbbbdddd", &previous(), 4),
+            ["aaaa", "bbbb", "dddd"]
+        );
+    }
+
+    #[test]
+    fn edits_at_either_end_keep_the_other_chunks() {
+        assert_eq!(
+            rechunk_text_unicode("Xaaaabbbbccccdddd", &previous(), 4),
+            ["X", "aaaa", "bbbb", "cccc", "dddd"]
+        );
+        assert_eq!(
+            rechunk_text_unicode("aaaabbbbccccddddX", &previous(), 4),
+            ["aaaa", "bbbb", "cccc", "dddd", "X"]
+        );
+    }
+
+    #[test]
+    fn head_and_tail_never_claim_the_same_text() {
+        let previous = vec!["ab".to_string(), "ab".to_string()];
+        assert_eq!(rechunk_text_unicode("ab", &previous, 2), ["ab"]);
+        assert_eq!(
+            rechunk_text_unicode("abXab", &previous, 2),
+            ["ab", "X", "ab"]
+        );
+    }
+}
@@ -1,29 +1,131 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use crate::services::storage::{AudioStorage, ObjectWriter};
 
 /// Naive MP3 concatenation:
-/// Copies the raw bytes of each input file into the output file in order,
-/// without decoding or re-encoding.
+/// Copies the audio of each input object into the output object in order,
+/// without decoding or re-encoding. ID3 tags are stripped from every
+/// input (see [`strip_id3_tags`]) so they don't end up mid-stream.
 ///
-/// Returns an `std::io::Result<()>` indicating success/failure.
+/// The output is streamed to storage one chunk at a time, so only a single
+/// chunk is held in memory however long the book is. If any chunk can't be
+/// copied, nothing is stored. Returns the size of the merged object.
 ///
 /// # Caveat
-/// This doesn't validate MP3 frames or remove ID3 tags.
-/// The resulting file may have playback issues if each chunk
-/// is a fully self-contained MP3 with its own headers.
-pub fn concat_mp3(input_files: &[&str], output_file: &str) -> std::io::Result<()> {
-    // Create or overwrite the output file
-    let mut out = BufWriter::new(File::create(output_file)?);
-
-    for &mp3_path in input_files {
-        // Read each chunk as raw bytes
-        let mut f = BufReader::new(File::open(mp3_path)?);
+/// This doesn't validate MP3 frames. The resulting file may have playback
+/// issues if the chunks were encoded with different parameters.
+pub async fn concat_mp3(
+    storage: &dyn AudioStorage,
+    input_keys: &[&str],
+    output_key: &str,
+) -> std::io::Result<u64> {
+    let mut writer = storage.writer(output_key).await?;
 
-        // Copy everything into output
-        std::io::copy(&mut f, &mut out)?;
+    match copy_chunks(storage, input_keys, writer.as_mut()).await {
+        Ok(()) => writer.finish().await,
+        Err(e) => {
+            if let Err(abort_error) = writer.abort().await {
+                println!("Failed to discard partial merge {output_key}: {abort_error}");
+            }
+            Err(e)
+        }
     }
+}
+
+async fn copy_chunks(
+    storage: &dyn AudioStorage,
+    input_keys: &[&str],
+    writer: &mut dyn ObjectWriter,
+) -> std::io::Result<()> {
+    for &key in input_keys {
+        // Read each chunk as raw bytes
+        let bytes = storage.get(key).await?;
 
-    // Flush ensures all data is written
-    out.flush()?;
+        // Copy the audio frames into output
+        writer.write(strip_id3_tags(&bytes)).await?;
+    }
     Ok(())
 }
+
+/// Returns the part of an MP3 file between a leading ID3v2 tag and a
+/// trailing ID3v1 tag, either of which may be absent.
+pub fn strip_id3_tags(data: &[u8]) -> &[u8] {
+    let mut start = 0;
+    if data.len() >= 10 && &data[..3] == b"ID3" {
+        // The tag size is a "syncsafe" integer: 7 bits per byte, excluding
+        // the 10-byte header and the optional 10-byte footer
+        let size = data[6..10]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 7) | (b & 0x7f) as usize);
+        let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+        start = (10 + size + footer).min(data.len());
+    }
+
+    let mut end = data.len();
+    if end - start >= 128 && &data[end - 128..end - 125] == b"TAG" {
+        end -= 128;
+    }
+
+    &data[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDIO: &[u8] = &[0xFF, 0xFB, 0x10, 0xC0, 1, 2, 3, 4];
+
+    /// An ID3v2 header announcing a tag of `size` bytes (syncsafe).
+    fn id3v2(size: [u8; 4], flags: u8) -> Vec<u8> {
+        let mut header = b"ID3\x04\x00".to_vec();
+        header.push(flags);
+        header.extend_from_slice(&size);
+        header
+    }
+
+    fn id3v1() -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.resize(128, b' ');
+        tag
+    }
+
+    #[test]
+    fn untagged_audio_is_kept_whole() {
+        assert_eq!(strip_id3_tags(AUDIO), AUDIO);
+        assert_eq!(strip_id3_tags(&[]), &[] as &[u8]);
+    }
+
+    #[test]
+    fn leading_tag_size_is_syncsafe() {
+        // 00 00 02 01 is 2 * 128 + 1 = 257 bytes, not 513
+        let mut data = id3v2([0, 0, 2, 1], 0);
+        data.resize(data.len() + 257, 0);
+        data.extend_from_slice(AUDIO);
+        assert_eq!(strip_id3_tags(&data), AUDIO);
+    }
+
+    #[test]
+    fn leading_tag_footer_is_skipped_too() {
+        let mut data = id3v2([0, 0, 0, 5], 0x10);
+        data.resize(data.len() + 5 + 10, 0);
+        data.extend_from_slice(AUDIO);
+        assert_eq!(strip_id3_tags(&data), AUDIO);
+    }
+
+    #[test]
+    fn trailing_tag_is_dropped() {
+        let mut data = AUDIO.to_vec();
+        data.extend(id3v1());
+        assert_eq!(strip_id3_tags(&data), AUDIO);
+
+        let mut both = id3v2([0, 0, 0, 3], 0);
+        both.extend_from_slice(&[0, 0, 0]);
+        both.extend_from_slice(&data);
+        assert_eq!(strip_id3_tags(&both), AUDIO);
+    }
+
+    #[test]
+    fn oversized_tag_is_clamped_to_the_data() {
+        let mut data = id3v2([0x7f, 0x7f, 0x7f, 0x7f], 0x10);
+        data.extend_from_slice(AUDIO);
+        assert_eq!(strip_id3_tags(&data), &[] as &[u8]);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod byte_range;
 pub mod chunk_text_unicode;
 pub mod concat_mp3;
+pub mod output_paths;
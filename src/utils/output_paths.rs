@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use std::path::{Component, Path};
+use uuid::Uuid;
+
+/// The storage key prefix a speech job writes its chunk and merged files
+/// under: `<user id>/<created at>-<job id>`, e.g.
+/// `42/20250321T122501Z-0b6f...`.
+///
+/// Every segment is built from an integer, a timestamp or a UUID, so
+/// nothing user-supplied ends up in the key, and the job ID keeps two jobs
+/// created in the same second apart. The timestamp has no colons, which
+/// some filesystems and archive tools reject.
+pub fn job_prefix(user_id: Option<i32>, job_id: Uuid, created_at: DateTime<Utc>) -> String {
+    let owner = user_id.map_or_else(|| "anonymous".to_string(), |id| id.to_string());
+    let stamp = created_at.format("%Y%m%dT%H%M%SZ");
+    format!("{owner}/{stamp}-{job_id}")
+}
+
+/// Whether `key` is a relative key made only of plain names, so it can't
+/// escape the storage root: `..`, `.`, absolute keys and drive prefixes are
+/// all rejected without touching the filesystem.
+pub fn is_safe_key(key: &str) -> bool {
+    let mut components = Path::new(key).components().peekable();
+    components.peek().is_some() && components.all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn unsafe_keys_are_rejected() {
+        for key in ["", "..", "a/../b", "./a", "/etc/passwd", "../speech.mp3"] {
+            assert!(!is_safe_key(key), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn job_keys_are_safe() {
+        let key = format!("42/20250321T122501Z-{}/speech-chunk-1.mp3", Uuid::new_v4());
+        assert!(is_safe_key(&key));
+        assert!(is_safe_key("cache/ab/abcdef.mp3"));
+    }
+
+    #[test]
+    fn job_prefix_is_unique_and_has_no_colons() {
+        let created_at = Utc.with_ymd_and_hms(2025, 3, 21, 12, 25, 1).unwrap();
+        let first = job_prefix(Some(42), Uuid::new_v4(), created_at);
+        let second = job_prefix(Some(42), Uuid::new_v4(), created_at);
+
+        assert!(first.starts_with("42/20250321T122501Z-"), "{first}");
+        assert!(!first.contains(':'));
+        assert_ne!(first, second);
+        assert!(is_safe_key(&first));
+        assert!(job_prefix(None, Uuid::new_v4(), created_at).starts_with("anonymous/"));
+    }
+}